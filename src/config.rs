@@ -0,0 +1,550 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use regex::Regex;
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+
+use crate::database::dump::Schema;
+use crate::database::rarbg::category::CategoryList;
+use crate::database::rarbg::swarm::RefreshOrder;
+use crate::torrent::udp::scrape::MAX_SCRAPE_HASHES;
+
+const DEFAULT_CONFIG_FILE: &str = "arrbg.toml";
+/// Keeps a round's stats within a single SQLite statement
+const MAX_SWARM_BATCH: u32 = 1000;
+/// Name the `[database]` dump goes by among the sources
+pub const PRIMARY_SOURCE: &str = "rarbg";
+
+/// Command line flags. Every flag can also be given through its environment
+/// variable, and both take precedence over the values in the config file.
+#[derive(Debug, Default, Parser)]
+#[command(author, version, about)]
+pub struct Args {
+    /// Path to a TOML config file. Defaults to `arrbg.toml` when it exists
+    #[arg(short, long, env = "ARRBG_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address the HTTP server binds to
+    #[arg(long, env = "ARRBG_LISTEN")]
+    pub listen: Option<SocketAddr>,
+    /// sqlx connection string for the RARBG dump
+    #[arg(long, env = "ARRBG_DATABASE_URL")]
+    pub database_url: Option<String>,
+    /// SQLite file holding everything arrbg adds to the dump
+    #[arg(long, env = "ARRBG_DATABASE_SIDECAR")]
+    pub database_sidecar: Option<PathBuf>,
+    /// Base URL of the Sonarr instance used for TVDB lookups
+    #[arg(long, env = "ARRBG_SONARR_URL")]
+    pub sonarr_url: Option<String>,
+    /// Sonarr API key
+    #[arg(long, env = "ARRBG_SONARR_API_KEY", hide_env_values = true)]
+    pub sonarr_api_key: Option<String>,
+    /// Base URL of the Radarr instance used for TMDB lookups
+    #[arg(long, env = "ARRBG_RADARR_URL")]
+    pub radarr_url: Option<String>,
+    /// Radarr API key. Without it, `search_themoviedb` is unavailable
+    #[arg(long, env = "ARRBG_RADARR_API_KEY", hide_env_values = true)]
+    pub radarr_api_key: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Builds the full-text index over every title in the dump. Run it again
+    /// whenever the dump changes.
+    Index,
+    /// Imports TVDB/TMDB to IMDb id mappings from a CSV file with an
+    /// `imdb_id` column and `tvdb_id` and/or `tmdb_id` (movie ids) columns
+    ImportIds { file: PathBuf },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub database: DatabaseConfig,
+    /// Further dumps searched along with `database`
+    pub sources: Vec<SourceConfig>,
+    pub sonarr: SonarrConfig,
+    pub radarr: RadarrConfig,
+    pub lookup_cache: LookupCacheConfig,
+    pub tokens: TokenConfig,
+    pub rate_limit: RateLimitConfig,
+    pub content_filter: ContentFilterConfig,
+    pub magnet: MagnetConfig,
+    pub swarm: SwarmConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// SQLite file for the full-text index and swarm stats, when the dump
+    /// itself must stay untouched. Created if missing.
+    pub sidecar: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    /// Shown when the source fails
+    pub name: String,
+    pub schema: Schema,
+    /// sqlx connection string, the dump is only ever read
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SonarrConfig {
+    pub url: String,
+    pub api_key: Option<Secret<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadarrConfig {
+    pub url: String,
+    /// Optional, unlike Sonarr's: only `search_themoviedb` needs Radarr
+    pub api_key: Option<Secret<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LookupCacheConfig {
+    /// Seconds a TVDB/TMDB id lookup is cached in memory
+    pub ttl: u64,
+    /// Seconds a lookup that found nothing is cached
+    pub negative_ttl: u64,
+    /// Lookups kept at most. When every one is still in flight, further
+    /// lookups skip the cache rather than grow it.
+    pub max_entries: usize,
+    /// Whether found ids are also stored in the `id_mappings` table, where
+    /// they outlive restarts
+    pub persist: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenConfig {
+    /// Whether `pubapi_v2.php` wants a token from `get_token`. Off unless
+    /// asked for, so clients that never fetched one keep working.
+    pub enabled: bool,
+    /// Seconds a token is valid
+    pub ttl: u64,
+    /// Signs the tokens. Without it a random secret is made on every start,
+    /// and tokens handed out before stop working.
+    pub secret: Option<Secret<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Off unless asked for: without tokens requests are told apart by IP,
+    /// and Sonarr, Radarr and Prowlarr behind one address would share it
+    pub enabled: bool,
+    /// Requests per second allowed per IP, and per token for clients that
+    /// bring one
+    pub rate: f64,
+    /// Requests that may come at once after a quiet spell
+    pub burst: u32,
+}
+
+/// What is never served, whatever the client asks for. Categories are given
+/// like in the `category` parameter: ids, names or groups such as `xxx`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentFilterConfig {
+    /// When not empty, the only categories served
+    pub allow_categories: Vec<String>,
+    pub deny_categories: Vec<String>,
+    /// Regular expressions, titles matching any of them are never served
+    pub deny_titles: Vec<String>,
+}
+
+impl ContentFilterConfig {
+    pub fn is_empty(&self) -> bool {
+        self.allow_categories.is_empty()
+            && self.deny_categories.is_empty()
+            && self.deny_titles.is_empty()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MagnetConfig {
+    /// Trackers appended as `tr=` to every magnet link
+    pub trackers: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwarmConfig {
+    /// Whether to scrape trackers for seeders/leechers in the background
+    pub enabled: bool,
+    /// UDP trackers to scrape. The highest counts reported by any of them are kept
+    pub trackers: Vec<String>,
+    pub order: RefreshOrder,
+    /// Items scraped per round
+    pub batch_size: u32,
+    /// Seconds after which stats are considered stale
+    pub max_age: u64,
+    /// Seconds to wait once every item is fresh
+    pub idle_interval: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 8000)),
+            database: Default::default(),
+            sources: Vec::new(),
+            sonarr: Default::default(),
+            radarr: Default::default(),
+            lookup_cache: Default::default(),
+            tokens: Default::default(),
+            rate_limit: Default::default(),
+            content_filter: Default::default(),
+            magnet: Default::default(),
+            swarm: Default::default(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: "sqlite:rarbg_db.sqlite".to_string(),
+            sidecar: None,
+        }
+    }
+}
+
+impl Default for SonarrConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:8989".to_string(),
+            api_key: None,
+        }
+    }
+}
+
+impl Default for RadarrConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:7878".to_string(),
+            api_key: None,
+        }
+    }
+}
+
+impl Default for LookupCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: 6 * 60 * 60,
+            negative_ttl: 5 * 60,
+            max_entries: 10_000,
+            persist: true,
+        }
+    }
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            // Like torrentapi's
+            ttl: 15 * 60,
+            secret: None,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            // torrentapi allowed one request every 2 seconds
+            rate: 0.5,
+            burst: 5,
+        }
+    }
+}
+
+impl Default for MagnetConfig {
+    fn default() -> Self {
+        Self {
+            trackers: vec![
+                "udp://tracker.opentrackr.org:1337/announce".to_string(),
+                "udp://open.demonii.com:1337/announce".to_string(),
+                "udp://tracker.torrent.eu.org:451/announce".to_string(),
+            ],
+        }
+    }
+}
+
+impl Default for SwarmConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            trackers: MagnetConfig::default().trackers,
+            order: RefreshOrder::default(),
+            batch_size: MAX_SCRAPE_HASHES as u32,
+            max_age: 24 * 60 * 60,
+            idle_interval: 10 * 60,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file (if any), applies environment variables and
+    /// command line flags on top of it and validates the result. Also returns
+    /// the subcommand to run instead of the server, if any.
+    pub fn load() -> anyhow::Result<(Self, Option<Command>)> {
+        let mut args = Args::parse();
+        let command = args.command.take();
+        Ok((Self::from_args(args)?, command))
+    }
+
+    pub fn from_args(args: Args) -> anyhow::Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        config.apply(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("parsing config file {}", path.display()))
+    }
+
+    fn apply(&mut self, args: Args) {
+        if let Some(listen) = args.listen {
+            self.listen = listen;
+        }
+        if let Some(url) = args.database_url {
+            self.database.url = url;
+        }
+        if let Some(sidecar) = args.database_sidecar {
+            self.database.sidecar = Some(sidecar);
+        }
+        if let Some(url) = args.sonarr_url {
+            self.sonarr.url = url;
+        }
+        if let Some(api_key) = args.sonarr_api_key {
+            self.sonarr.api_key = Some(Secret::new(api_key));
+        }
+        if let Some(url) = args.radarr_url {
+            self.radarr.url = url;
+        }
+        if let Some(api_key) = args.radarr_api_key {
+            self.radarr.api_key = Some(Secret::new(api_key));
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        if !self.database.url.starts_with("sqlite:") {
+            errors.push(format!(
+                "database.url must be a sqlite connection string, got '{}'",
+                self.database.url
+            ));
+        }
+        let mut names = vec![PRIMARY_SOURCE];
+        for source in &self.sources {
+            if source.name.trim().is_empty() {
+                errors.push("sources: every source needs a name".to_string());
+            } else if names.contains(&source.name.as_str()) {
+                errors.push(format!("sources: the name '{}' is taken", source.name));
+            }
+            names.push(&source.name);
+            if !source.url.starts_with("sqlite:") {
+                errors.push(format!(
+                    "sources: the url of '{}' must be a sqlite connection string, got '{}'",
+                    source.name, source.url
+                ));
+            }
+        }
+        if let Err(e) = Url::parse(&self.sonarr.url) {
+            errors.push(format!(
+                "sonarr.url '{}' is invalid: {}",
+                self.sonarr.url, e
+            ));
+        }
+        match &self.sonarr.api_key {
+            Some(key) if !key.expose_secret().trim().is_empty() => {}
+            _ => errors.push("sonarr.api_key is required".to_string()),
+        }
+        if self.radarr.api_key.is_some() {
+            if let Err(e) = Url::parse(&self.radarr.url) {
+                errors.push(format!(
+                    "radarr.url '{}' is invalid: {}",
+                    self.radarr.url, e
+                ));
+            }
+        }
+        if self.lookup_cache.max_entries == 0 {
+            errors.push("lookup_cache.max_entries must be at least 1".to_string());
+        }
+        if self.tokens.enabled && self.tokens.ttl == 0 {
+            errors.push("tokens.ttl must be at least 1".to_string());
+        }
+        if self.rate_limit.enabled {
+            if !(self.rate_limit.rate > 0.0 && self.rate_limit.rate.is_finite()) {
+                errors.push("rate_limit.rate must be above 0".to_string());
+            }
+            if self.rate_limit.burst == 0 {
+                errors.push("rate_limit.burst must be at least 1".to_string());
+            }
+        }
+        let content_categories = [
+            ("allow_categories", &self.content_filter.allow_categories),
+            ("deny_categories", &self.content_filter.deny_categories),
+        ];
+        for (field, categories) in content_categories {
+            for category in categories {
+                if let Err(e) = category.parse::<CategoryList>() {
+                    errors.push(format!("content_filter.{}: {}", field, e));
+                }
+            }
+        }
+        for pattern in &self.content_filter.deny_titles {
+            if let Err(e) = Regex::new(pattern) {
+                errors.push(format!("content_filter.deny_titles: {}", e));
+            }
+        }
+        for tracker in &self.magnet.trackers {
+            if let Err(e) = Url::parse(tracker) {
+                errors.push(format!("magnet.trackers: '{}' is invalid: {}", tracker, e));
+            }
+        }
+
+        if self.swarm.enabled {
+            if self.swarm.trackers.is_empty() {
+                errors.push("swarm.trackers can't be empty when swarm.enabled".to_string());
+            }
+            for tracker in &self.swarm.trackers {
+                match Url::parse(tracker) {
+                    Ok(url) if url.scheme() == "udp" => {}
+                    _ => errors.push(format!(
+                        "swarm.trackers: '{}' is not a udp:// tracker URL",
+                        tracker
+                    )),
+                }
+            }
+            if !(1..=MAX_SWARM_BATCH).contains(&self.swarm.batch_size) {
+                errors.push(format!(
+                    "swarm.batch_size must be between 1 and {}",
+                    MAX_SWARM_BATCH
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("invalid configuration:\n  {}", errors.join("\n  "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use secrecy::ExposeSecret;
+
+    use super::{Args, Config};
+
+    fn write_config(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn args_override_file() {
+        let file = write_config(
+            r#"
+            listen = "127.0.0.1:9000"
+
+            [database]
+            url = "sqlite:from_file.sqlite"
+
+            [sonarr]
+            url = "http://sonarr:8989"
+            api_key = "from-file"
+            "#,
+        );
+
+        let config = Config::from_args(Args {
+            config: Some(file.path().to_path_buf()),
+            sonarr_api_key: Some("from-args".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(config.listen.to_string(), "127.0.0.1:9000");
+        assert_eq!(config.database.url, "sqlite:from_file.sqlite");
+        assert_eq!(config.sonarr.url, "http://sonarr:8989");
+        assert_eq!(config.sonarr.api_key.unwrap().expose_secret(), "from-args");
+    }
+
+    #[test]
+    fn reports_every_invalid_field() {
+        let file = write_config(
+            r#"
+            [database]
+            url = "postgres://localhost"
+
+            [sonarr]
+            url = "not a url"
+
+            [radarr]
+            url = "not a url either"
+            api_key = "set"
+
+            [rate_limit]
+            enabled = true
+            rate = 0.0
+
+            [content_filter]
+            deny_categories = ["xxx", "porn"]
+            deny_titles = ["(unclosed"]
+
+            [[sources]]
+            name = "rarbg"
+            schema = "magnetico"
+            url = "/var/lib/magnetico.sqlite3"
+            "#,
+        );
+
+        let err = Config::from_args(Args {
+            config: Some(file.path().to_path_buf()),
+            ..Default::default()
+        })
+        .unwrap_err()
+        .to_string();
+
+        assert!(err.contains("database.url"), "{err}");
+        assert!(err.contains("sonarr.url"), "{err}");
+        assert!(err.contains("sonarr.api_key"), "{err}");
+        assert!(err.contains("radarr.url"), "{err}");
+        assert!(err.contains("rate_limit.rate"), "{err}");
+        assert!(err.contains("content_filter.deny_categories"), "{err}");
+        assert!(err.contains("content_filter.deny_titles"), "{err}");
+        assert!(err.contains("'rarbg' is taken"), "{err}");
+        assert!(err.contains("url of 'rarbg'"), "{err}");
+    }
+}
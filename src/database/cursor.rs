@@ -0,0 +1,160 @@
+//! Where a page of results starts. Searches that can seek continue after
+//! the sort key and id of the last item they returned, so a page costs the
+//! same however deep it is. `Skip` is the fallback for everything that can
+//! only count items, and what plain offsets turn into.
+//!
+//! Cursors are passed around as text: `last:<id>`, `seeders:<count>:<id>`,
+//! `leechers:<count>:<id>`, `relevance:<rank>:<id>` and `skip:<items>`, with
+//! `none` for items without a count or rank.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::Context;
+
+use super::Sort;
+
+/// Sort key of the last item of a page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Last,
+    /// `None` for items that were never scraped
+    Seeders(Option<i64>),
+    Leechers(Option<i64>),
+    /// The full-text rank, `None` when the search wasn't ranked
+    Relevance(Option<f64>),
+}
+
+impl SortKey {
+    pub fn sort(&self) -> Sort {
+        match self {
+            SortKey::Last => Sort::Last,
+            SortKey::Seeders(_) => Sort::Seeders,
+            SortKey::Leechers(_) => Sort::Leechers,
+            SortKey::Relevance(_) => Sort::Relevance,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cursor {
+    /// Continues after the item with `id`, which sorted at `key`
+    After { key: SortKey, id: i64 },
+    /// Continues after this many items
+    Skip(usize),
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn value<T: Display>(value: &Option<T>) -> String {
+            value
+                .as_ref()
+                .map_or_else(|| "none".to_string(), T::to_string)
+        }
+
+        match self {
+            Cursor::After { key, id } => match key {
+                SortKey::Last => write!(f, "last:{id}"),
+                SortKey::Seeders(count) => write!(f, "seeders:{}:{id}", value(count)),
+                SortKey::Leechers(count) => write!(f, "leechers:{}:{id}", value(count)),
+                SortKey::Relevance(rank) => write!(f, "relevance:{}:{id}", value(rank)),
+            },
+            Cursor::Skip(items) => write!(f, "skip:{items}"),
+        }
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s).with_context(|| format!("invalid cursor '{s}'"))
+    }
+}
+
+fn parse(s: &str) -> anyhow::Result<Cursor> {
+    fn optional<T: FromStr>(value: &str) -> anyhow::Result<Option<T>>
+    where
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        match value {
+            "none" => Ok(None),
+            value => Ok(Some(value.parse()?)),
+        }
+    }
+
+    let parts = s.split(':').collect::<Vec<_>>();
+    let cursor = match parts[..] {
+        ["skip", items] => Cursor::Skip(items.parse()?),
+        ["last", id] => Cursor::After {
+            key: SortKey::Last,
+            id: id.parse()?,
+        },
+        [sort, value, id] => Cursor::After {
+            key: match sort {
+                "seeders" => SortKey::Seeders(optional(value)?),
+                "leechers" => SortKey::Leechers(optional(value)?),
+                "relevance" => SortKey::Relevance(optional(value)?),
+                _ => anyhow::bail!("unknown sort '{sort}'"),
+            },
+            id: id.parse()?,
+        },
+        _ => anyhow::bail!("malformed"),
+    };
+    Ok(cursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cursor, SortKey};
+
+    #[test]
+    fn round_trips_cursors() {
+        let cursors = [
+            Cursor::After {
+                key: SortKey::Last,
+                id: 42,
+            },
+            Cursor::After {
+                key: SortKey::Seeders(Some(7)),
+                id: 1,
+            },
+            Cursor::After {
+                key: SortKey::Leechers(None),
+                id: 3,
+            },
+            Cursor::After {
+                key: SortKey::Relevance(Some(-1.25e-6)),
+                id: 9,
+            },
+            Cursor::After {
+                key: SortKey::Relevance(None),
+                id: 9,
+            },
+            Cursor::Skip(100),
+        ];
+        for cursor in cursors {
+            assert_eq!(cursor.to_string().parse::<Cursor>().unwrap(), cursor);
+        }
+
+        assert_eq!(
+            Cursor::After {
+                key: SortKey::Seeders(None),
+                id: 5
+            }
+            .to_string(),
+            "seeders:none:5"
+        );
+        for invalid in [
+            "",
+            "last",
+            "last:x",
+            "skip:-1",
+            "size:1:2",
+            "seeders:1",
+            "last:1:2",
+        ] {
+            assert!(invalid.parse::<Cursor>().is_err(), "{invalid}");
+        }
+    }
+}
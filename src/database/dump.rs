@@ -0,0 +1,319 @@
+//! Torrent dumps other than RARBG's, such as magnetico databases and SQLite
+//! exports of bitmagnet. Each schema has an adapter selecting its torrents
+//! as `Item`s. Dumps are opened read-only, so they have no swarm stats or id
+//! mappings of their own.
+
+use std::str::FromStr;
+
+use axum::async_trait;
+use serde::Deserialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{QueryBuilder, SqlitePool};
+
+use crate::config::SourceConfig;
+use crate::release::{Release, Resolution, Source, VideoCodec};
+
+use super::rarbg::category::Category;
+use super::rarbg::query::SearchQuery;
+use super::{filter_pages, Item, Pagination, SearchRequest, SearchResults, Sort};
+
+/// Layout of a dump
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Schema {
+    /// The `torrents` table magnetico fills from the DHT
+    Magnetico,
+    /// bitmagnet's `torrents` and `torrents_torrent_sources` tables, copied
+    /// over to SQLite
+    Bitmagnet,
+}
+
+impl Schema {
+    /// As in the config
+    fn name(&self) -> &'static str {
+        match self {
+            Schema::Magnetico => "magnetico",
+            Schema::Bitmagnet => "bitmagnet",
+        }
+    }
+
+    /// A `SELECT` giving every torrent of the dump the columns of `Item`.
+    /// Infohashes are stored as blobs in both.
+    fn items(&self) -> &'static str {
+        match self {
+            Schema::Magnetico => {
+                "SELECT id, name AS title, lower(hex(info_hash)) AS hash, \
+                 datetime(discovered_on, 'unixepoch') AS dt, '' AS cat, NULL AS imdb, \
+                 total_size AS size, NULL AS ext_id, NULL AS seeders, NULL AS leechers \
+                 FROM torrents"
+            }
+            Schema::Bitmagnet => {
+                "SELECT rowid AS id, name AS title, lower(hex(info_hash)) AS hash, \
+                 coalesce(strftime('%Y-%m-%d %H:%M:%S', created_at), '') AS dt, '' AS cat, \
+                 NULL AS imdb, size, NULL AS ext_id, \
+                 (SELECT max(seeders) FROM torrents_torrent_sources AS s \
+                  WHERE s.info_hash = torrents.info_hash) AS seeders, \
+                 (SELECT max(leechers) FROM torrents_torrent_sources AS s \
+                  WHERE s.info_hash = torrents.info_hash) AS leechers \
+                 FROM torrents"
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Dump {
+    db: SqlitePool,
+    schema: Schema,
+}
+
+impl Dump {
+    pub fn new(db: SqlitePool, schema: Schema) -> Self {
+        Self { db, schema }
+    }
+
+    pub async fn connect(config: &SourceConfig) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(&config.url)?.read_only(true);
+        let db = SqlitePoolOptions::new().connect_with(options).await?;
+        Ok(Self::new(db, config.schema))
+    }
+
+    async fn search_page(
+        &self,
+        search: &SearchQuery,
+        request: &SearchRequest,
+        pagination: Pagination,
+    ) -> anyhow::Result<Vec<Item>> {
+        let mut query = QueryBuilder::new("SELECT * FROM (");
+        query.push(self.schema.items()).push(") WHERE TRUE");
+        search.push_conditions(&mut query, None);
+        if let Some(imdb_id) = &request.imdb_id {
+            // Never matches, dumps know no IMDb ids
+            query.push(" AND imdb = ").push_bind(imdb_id.clone());
+        }
+        if let Some(min_seeders) = request.min_seeders {
+            query.push(" AND seeders >= ").push_bind(min_seeders);
+        }
+        query.push(match request.sort {
+            Sort::Last | Sort::Relevance => " ORDER BY dt DESC, id DESC",
+            Sort::Seeders => " ORDER BY seeders DESC NULLS LAST, dt DESC",
+            Sort::Leechers => " ORDER BY leechers DESC NULLS LAST, dt DESC",
+        });
+        query.push(" LIMIT ").push_bind(pagination.amount() as u32);
+        query
+            .push(" OFFSET ")
+            .push_bind(pagination.skipped() as u32);
+
+        let mut items: Vec<Item> = query.build_query_as().fetch_all(&self.db).await?;
+        for item in &mut items {
+            item.cat = guess_category(&item.release())
+                .map(|c| c.name())
+                .unwrap_or_default();
+        }
+        Ok(items)
+    }
+}
+
+#[async_trait]
+impl super::Database for Dump {
+    async fn search(&self, request: &SearchRequest) -> anyhow::Result<Vec<Item>> {
+        Ok(self.search_sources(request).await?.items)
+    }
+
+    /// Categories are guessed from the release names, so like the release
+    /// filter they are applied page by page. Filtered searches giving up
+    /// report it as a failure, as they can't resume.
+    async fn search_sources(&self, request: &SearchRequest) -> anyhow::Result<SearchResults> {
+        request.pagination.ensure_skipping()?;
+        let filter = &request.release;
+        let categories = request.categories.as_deref();
+        let mut text = request.text.clone();
+        if !filter.is_empty() {
+            text.push(' ');
+            text.push_str(&filter.search_terms());
+        }
+        let search = SearchQuery::parse(&text);
+        if filter.is_empty() && categories.is_none() {
+            return Ok(SearchResults {
+                items: self
+                    .search_page(&search, request, request.pagination)
+                    .await?,
+                ..Default::default()
+            });
+        }
+
+        let filtered = filter_pages(
+            request.pagination,
+            |item| {
+                categories.is_none_or(|c| c.iter().any(|c| c.name() == item.cat))
+                    && filter.matches(&item.release())
+            },
+            |pagination| self.search_page(&search, request, pagination),
+            // Skipping can't resume past the items it looked at
+            |_, _| None,
+        )
+        .await?;
+        Ok(filtered.into_results(self.schema.name()))
+    }
+}
+
+/// The RARBG category a release would have been filed under. Only TV and
+/// movies can be told apart by their names.
+fn guess_category(release: &Release) -> Option<Category> {
+    let resolution = release.resolution;
+    if !release.seasons.is_empty() {
+        return Some(match resolution {
+            Some(Resolution::R2160p) => Category::TvUhdEpisodes,
+            Some(Resolution::R720p | Resolution::R1080p) => Category::TvHdEpisodes,
+            _ => Category::TvEpisodes,
+        });
+    }
+    release.year?;
+
+    let x265 = release.video_codec == Some(VideoCodec::X265);
+    let xvid = release.video_codec == Some(VideoCodec::Xvid);
+    Some(match resolution {
+        Some(Resolution::R2160p) if x265 && !release.hdr.is_empty() => Category::MoviesX265_4kHdr,
+        Some(Resolution::R2160p) if x265 => Category::MoviesX265_4k,
+        Some(Resolution::R2160p) => Category::MoviesX264_4k,
+        _ if release.source == Some(Source::Remux) => Category::MoviesBdRemux,
+        Some(Resolution::R1080p) if x265 => Category::MoviesX265_1080,
+        Some(Resolution::R1080p) => Category::MoviesX264_1080,
+        Some(Resolution::R720p) if xvid => Category::MoviesXvid720,
+        Some(Resolution::R720p) => Category::MoviesX264_720,
+        _ if xvid => Category::MoviesXvid,
+        _ => Category::MoviesX264,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dump, Schema};
+    use crate::database::rarbg::category::Category;
+    use crate::database::{test_pool, Database, SearchRequest, Sort};
+
+    async fn dump(schema: Schema, sql: &str) -> Dump {
+        let pool = test_pool().await;
+        sqlx::query(sql).execute(&pool).await.unwrap();
+        Dump::new(pool, schema)
+    }
+
+    async fn titles(dump: &Dump, request: SearchRequest) -> Vec<(String, String, String)> {
+        dump.search(&request)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| (item.title, item.hash, item.cat))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn searches_magnetico() {
+        let dump = dump(
+            Schema::Magnetico,
+            r#"
+            CREATE TABLE torrents (
+                id INTEGER PRIMARY KEY, info_hash BLOB, name TEXT,
+                total_size INTEGER, discovered_on INTEGER
+            );
+            INSERT INTO torrents (info_hash, name, total_size, discovered_on) VALUES
+                (X'0101010101010101010101010101010101010101', 'Show.S01E01.720p.WEB.x264-GRP', 1, 1600000000),
+                (X'ABABABABABABABABABABABABABABABABABABABAB', 'The.Movie.2020.2160p.BluRay.x265.HDR-GRP', 2, 1700000000),
+                (X'0303030303030303030303030303030303030303', 'holiday photos', 3, 1650000000);
+            "#,
+        )
+        .await;
+
+        let found = titles(&dump, SearchRequest::default()).await;
+        assert_eq!(
+            found.iter().map(|(t, _, _)| t.as_str()).collect::<Vec<_>>(),
+            [
+                "The.Movie.2020.2160p.BluRay.x265.HDR-GRP",
+                "holiday photos",
+                "Show.S01E01.720p.WEB.x264-GRP"
+            ]
+        );
+        assert_eq!(found[0].1, "abababababababababababababababababababab");
+        assert_eq!(found[0].2, "movies_x265_4k_hdr");
+        assert_eq!(found[1].2, "");
+
+        let tv = SearchRequest {
+            text: "show".to_string(),
+            categories: Some(vec![Category::TvHdEpisodes]),
+            ..Default::default()
+        };
+        assert_eq!(titles(&dump, tv).await.len(), 1);
+        let movies = SearchRequest {
+            categories: Some(vec![Category::MoviesX264_1080]),
+            ..Default::default()
+        };
+        assert!(titles(&dump, movies).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn searches_bitmagnet() {
+        let dump = dump(
+            Schema::Bitmagnet,
+            r#"
+            CREATE TABLE torrents (info_hash BLOB, name TEXT, size INTEGER, created_at TEXT);
+            CREATE TABLE torrents_torrent_sources (
+                source TEXT, info_hash BLOB, seeders INTEGER, leechers INTEGER
+            );
+            INSERT INTO torrents VALUES
+                (X'0101010101010101010101010101010101010101', 'Old.Show.S02E03.HDTV', 1, '2021-01-01 10:00:00+00:00'),
+                (X'0202020202020202020202020202020202020202', 'New.Show.S01E01.1080p.WEB', 1, '2023-01-01 10:00:00+00:00');
+            INSERT INTO torrents_torrent_sources VALUES
+                ('dht', X'0101010101010101010101010101010101010101', 50, 4),
+                ('tracker', X'0101010101010101010101010101010101010101', 80, 2);
+            "#,
+        )
+        .await;
+
+        let by_seeders = SearchRequest {
+            sort: Sort::Seeders,
+            ..Default::default()
+        };
+        let found = dump.search(&by_seeders).await.unwrap();
+        assert_eq!(found[0].title, "Old.Show.S02E03.HDTV");
+        assert_eq!(found[0].seeders, Some(80));
+        assert_eq!(found[0].dt, "2021-01-01 10:00:00");
+        assert_eq!(found[1].seeders, None);
+
+        let popular = SearchRequest {
+            min_seeders: Some(60),
+            ..Default::default()
+        };
+        assert_eq!(titles(&dump, popular).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reports_category_searches_that_gave_up() {
+        // The only movie is the oldest, behind more than the filtered pages
+        // look through
+        let dump = dump(
+            Schema::Magnetico,
+            r#"
+            CREATE TABLE torrents (
+                id INTEGER PRIMARY KEY, info_hash BLOB, name TEXT,
+                total_size INTEGER, discovered_on INTEGER
+            );
+            INSERT INTO torrents (id, info_hash, name, total_size, discovered_on) VALUES
+                (1, X'0101010101010101010101010101010101010101', 'The.Movie.2020.1080p.BluRay.x264-GRP', 1, 1);
+            WITH RECURSIVE n(id) AS (SELECT 2 UNION ALL SELECT id + 1 FROM n WHERE id < 2101)
+            INSERT INTO torrents (id, info_hash, name, total_size, discovered_on)
+                SELECT id, randomblob(20), 'holiday photos', 1, id FROM n;
+            "#,
+        )
+        .await;
+
+        let movies = SearchRequest {
+            categories: Some(vec![Category::MoviesX264_1080]),
+            ..Default::default()
+        };
+        let results = dump.search_sources(&movies).await.unwrap();
+        assert!(results.items.is_empty());
+        assert_eq!(results.resume, None);
+        assert_eq!(results.failures.len(), 1);
+        assert_eq!(results.failures[0].source, "magnetico");
+    }
+}
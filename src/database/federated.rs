@@ -0,0 +1,418 @@
+//! Several databases searched as one. Every source is asked concurrently,
+//! and their results merged, deduplicated by infohash, sorted and paginated
+//! as a whole.
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::async_trait;
+use tracing::warn;
+
+use super::ids::{IdMapping, IdSource};
+use super::{
+    Database, Item, Pagination, SearchError, SearchRequest, SearchResults, Sort, SourceFailure,
+    MAX_RESULTS, MAX_SKIPPED,
+};
+
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub db: Arc<dyn Database>,
+}
+
+#[derive(Debug)]
+pub struct Federation {
+    /// The first one is preferred whenever sources agree
+    sources: Vec<Source>,
+}
+
+impl Federation {
+    pub fn new(sources: Vec<Source>) -> Self {
+        Self { sources }
+    }
+}
+
+#[async_trait]
+impl Database for Federation {
+    async fn search(&self, request: &SearchRequest) -> anyhow::Result<Vec<Item>> {
+        let results = self.search_sources(request).await?;
+        for failure in results.failures {
+            warn!("Source {} failed: {}", failure.source, failure.error);
+        }
+        Ok(results.items)
+    }
+
+    /// Fails only when every source does
+    async fn search_sources(&self, request: &SearchRequest) -> anyhow::Result<SearchResults> {
+        let pagination = request.pagination;
+        pagination.ensure_skipping()?;
+        // Every source must deliver enough results to fill the page after
+        // the ones before it were merged in
+        let skipped = pagination.skipped();
+        if skipped > MAX_SKIPPED {
+            return Err(SearchError::InvalidRequest(format!(
+                "can't skip more than {MAX_SKIPPED} results"
+            ))
+            .into());
+        }
+        let wanted = skipped + pagination.amount();
+        let searches = self
+            .sources
+            .iter()
+            .map(|source| {
+                let db = source.db.clone();
+                let request = request.clone();
+                tokio::spawn(async move { first_items(db.as_ref(), request, wanted).await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut items = Vec::new();
+        let mut failures = Vec::new();
+        let mut failed = 0;
+        for (index, (source, search)) in self.sources.iter().zip(searches).enumerate() {
+            match search.await.map_err(anyhow::Error::from).and_then(|r| r) {
+                // Along with their rank within the source
+                Ok(found) => {
+                    items.extend(found.items.into_iter().enumerate().map(|(rank, mut item)| {
+                        item.source = Some(index as u32);
+                        (rank, item)
+                    }));
+                    // Such as searches giving up, under the configured name
+                    failures.extend(found.failures.into_iter().map(|failure| SourceFailure {
+                        source: source.name.clone(),
+                        ..failure
+                    }));
+                }
+                Err(e) => {
+                    failed += 1;
+                    failures.push(SourceFailure {
+                        source: source.name.clone(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        if failed > 0 && failed == self.sources.len() {
+            return Err(SearchError::Unavailable(failures).into());
+        }
+
+        sort(&mut items, request.sort);
+        let mut seen = HashSet::new();
+        let items = items
+            .into_iter()
+            .map(|(_, item)| item)
+            .filter(|item| seen.insert(item.hash.trim().to_lowercase()))
+            .skip(skipped)
+            .take(pagination.amount())
+            .collect();
+        Ok(SearchResults {
+            items,
+            failures,
+            resume: None,
+        })
+    }
+
+    async fn imdb_id(&self, source: IdSource, external_id: &str) -> anyhow::Result<Option<String>> {
+        for s in &self.sources {
+            if let Some(imdb_id) = s.db.imdb_id(source, external_id).await? {
+                return Ok(Some(imdb_id));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Hits are counted by the sources the items were found in
+    async fn record_hits(&self, items: &[Item]) -> anyhow::Result<()> {
+        for (index, source) in self.sources.iter().enumerate() {
+            let found = items
+                .iter()
+                .filter(|item| item.source == Some(index as u32))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !found.is_empty() {
+                source.db.record_hits(&found).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mappings are kept by the first source
+    async fn store_id_mappings(&self, mappings: &[IdMapping]) -> anyhow::Result<u64> {
+        match self.sources.first() {
+            Some(source) => source.db.store_id_mappings(mappings).await,
+            None => Ok(0),
+        }
+    }
+}
+
+/// The first `wanted` results of a source, page by page, along with what
+/// the source reported failing
+async fn first_items(
+    db: &dyn Database,
+    request: SearchRequest,
+    wanted: usize,
+) -> anyhow::Result<SearchResults> {
+    let mut items = Vec::new();
+    let mut failures = Vec::new();
+    while items.len() < wanted {
+        let amount = (wanted - items.len()).min(MAX_RESULTS);
+        let page = db
+            .search_sources(&SearchRequest {
+                pagination: Pagination {
+                    offset: items.len(),
+                    amount: Some(amount),
+                    after: None,
+                },
+                ..request.clone()
+            })
+            .await?;
+        let exhausted = page.items.len() < amount;
+        items.extend(page.items);
+        failures.extend(page.failures);
+        if exhausted {
+            break;
+        }
+    }
+    Ok(SearchResults {
+        items,
+        failures,
+        resume: None,
+    })
+}
+
+/// Sorts results of several sources, each found at the given rank. Ranks
+/// from different sources can't be compared beyond that, so sorting by
+/// relevance takes turns between sources. The sort is stable, so ties keep
+/// the order of the sources. Dates are compared parsed, as sources write
+/// them in shapes of their own.
+fn sort(items: &mut [(usize, Item)], sort: Sort) {
+    // Descending, and `None` sorts before any number or date, so unscraped
+    // items and unreadable dates go last
+    match sort {
+        Sort::Last => items.sort_by_cached_key(|(_, item)| Reverse(item.published())),
+        Sort::Relevance => items.sort_by_key(|(rank, _)| *rank),
+        Sort::Seeders => {
+            items.sort_by_cached_key(|(_, item)| Reverse((item.seeders, item.published())))
+        }
+        Sort::Leechers => {
+            items.sort_by_cached_key(|(_, item)| Reverse((item.leechers, item.published())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::async_trait;
+
+    use super::{Federation, Source};
+    use crate::database::cursor::Cursor;
+    use crate::database::dump::{Dump, Schema};
+    use crate::database::rarbg::test_db;
+    use crate::database::{
+        test_pool, Database, Item, Pagination, SearchError, SearchRequest, Sort,
+    };
+
+    #[derive(Debug)]
+    struct Offline;
+
+    #[async_trait]
+    impl Database for Offline {
+        async fn search(&self, _request: &SearchRequest) -> anyhow::Result<Vec<Item>> {
+            Err(anyhow::anyhow!("connection refused"))
+        }
+    }
+
+    /// Remembers the titles it counted hits for
+    #[derive(Debug)]
+    struct Counting {
+        db: Arc<dyn Database>,
+        hits: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Database for Counting {
+        async fn search(&self, request: &SearchRequest) -> anyhow::Result<Vec<Item>> {
+            self.db.search(request).await
+        }
+
+        async fn record_hits(&self, items: &[Item]) -> anyhow::Result<()> {
+            let mut hits = self.hits.lock().unwrap();
+            hits.extend(items.iter().map(|item| item.title.clone()));
+            Ok(())
+        }
+    }
+
+    async fn magnetico(rows: &str) -> Arc<dyn Database> {
+        let pool = test_pool().await;
+        sqlx::query(&format!(
+            "CREATE TABLE torrents (
+                id INTEGER PRIMARY KEY, info_hash BLOB, name TEXT,
+                total_size INTEGER, discovered_on INTEGER
+            );
+            INSERT INTO torrents (info_hash, name, discovered_on) VALUES {rows};"
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+        Arc::new(Dump::new(pool, Schema::Magnetico))
+    }
+
+    fn source(name: &str, db: Arc<dyn Database>) -> Source {
+        Source {
+            name: name.to_string(),
+            db,
+        }
+    }
+
+    #[tokio::test]
+    async fn merges_sources() {
+        let federation = Federation::new(vec![
+            source(
+                "first",
+                magnetico(
+                    "(X'0101010101010101010101010101010101010101', 'one', 100),
+                     (X'0303030303030303030303030303030303030303', 'three', 300)",
+                )
+                .await,
+            ),
+            source("offline", Arc::new(Offline)),
+            source(
+                "second",
+                magnetico(
+                    "(X'0202020202020202020202020202020202020202', 'two', 200),
+                     (X'0303030303030303030303030303030303030303', 'three again', 300),
+                     (X'0404040404040404040404040404040404040404', 'four', 400)",
+                )
+                .await,
+            ),
+        ]);
+
+        let titles = |request: SearchRequest| {
+            let federation = &federation;
+            async move {
+                let results = federation.search_sources(&request).await.unwrap();
+                let titles = results
+                    .items
+                    .into_iter()
+                    .map(|item| item.title)
+                    .collect::<Vec<_>>();
+                (titles, results.failures)
+            }
+        };
+
+        let (found, failures) = titles(SearchRequest::default()).await;
+        assert_eq!(found, ["four", "three", "two", "one"]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].source, "offline");
+        assert_eq!(failures[0].error, "connection refused");
+
+        let skipping_one = SearchRequest {
+            pagination: Pagination {
+                offset: 1,
+                amount: Some(2),
+                after: None,
+            },
+            ..Default::default()
+        };
+        assert_eq!(titles(skipping_one).await.0, ["three", "two"]);
+
+        let too_deep = SearchRequest {
+            pagination: Pagination {
+                after: Some(Cursor::Skip(1_000_000)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let err = federation.search_sources(&too_deep).await.err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<SearchError>(),
+            Some(SearchError::InvalidRequest(_))
+        ));
+
+        let relevance = SearchRequest {
+            sort: Sort::Relevance,
+            ..Default::default()
+        };
+        assert_eq!(titles(relevance).await.0, ["three", "four", "one", "two"]);
+
+        let offline = Federation::new(vec![source("offline", Arc::new(Offline))]);
+        let err = offline
+            .search(&SearchRequest::default())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "every source failed: offline (connection refused)"
+        );
+        assert!(matches!(
+            err.downcast_ref::<SearchError>(),
+            Some(SearchError::Unavailable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn orders_dates_of_every_source_alike() {
+        let rarbg = test_db(
+            r#"
+            INSERT INTO items (id, hash, title, dt, cat) VALUES
+                (1, '0101010101010101010101010101010101010101', 'rarbg 12:00', '2021-01-01T12:00:00Z', 'movies'),
+                (2, '0202020202020202020202020202020202020202', 'rarbg 12:30', '2021-01-01 14:30:00+02:00', 'movies'),
+                (3, '0303030303030303030303030303030303030303', 'rarbg 10:00', '2021-01-01 10:00:00.250', 'movies');
+            "#,
+        )
+        .await;
+        let federation = Federation::new(vec![
+            source("rarbg", Arc::new(rarbg)),
+            source(
+                "magnetico",
+                magnetico(
+                    "(X'0404040404040404040404040404040404040404', 'magnetico 13:00', 1609506000),
+                     (X'0505050505050505050505050505050505050505', 'magnetico 11:00', 1609498800)",
+                )
+                .await,
+            ),
+        ]);
+
+        let items = federation.search(&SearchRequest::default()).await.unwrap();
+        let titles = items.into_iter().map(|item| item.title).collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            [
+                "magnetico 13:00",
+                "rarbg 12:30",
+                "rarbg 12:00",
+                "magnetico 11:00",
+                "rarbg 10:00"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_hits_where_items_were_found() {
+        // Both dumps number their items from 1
+        let counting = |db| {
+            Arc::new(Counting {
+                db,
+                hits: Mutex::new(Vec::new()),
+            })
+        };
+        let first =
+            counting(magnetico("(X'0101010101010101010101010101010101010101', 'one', 100)").await);
+        let second =
+            counting(magnetico("(X'0202020202020202020202020202020202020202', 'two', 200)").await);
+        let federation = Federation::new(vec![
+            source("first", first.clone()),
+            source("second", second.clone()),
+        ]);
+
+        let items = federation.search(&SearchRequest::default()).await.unwrap();
+        federation.record_hits(&items).await.unwrap();
+
+        assert_eq!(*first.hits.lock().unwrap(), ["one"]);
+        assert_eq!(*second.hits.lock().unwrap(), ["two"]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Content an instance never serves, whatever the client asks for. The
+//! filter wraps the database every API searches: categories are narrowed
+//! down in the query itself, titles are matched page by page.
+
+use std::sync::{Arc, Mutex};
+
+use axum::async_trait;
+use regex::Regex;
+use tracing::warn;
+
+use crate::config::ContentFilterConfig;
+
+use super::cursor::Cursor;
+use super::ids::{IdMapping, IdSource};
+use super::rarbg::category::{Category, CategoryList};
+use super::{filter_pages, Database, Item, SearchRequest, SearchResults};
+
+#[derive(Debug)]
+pub struct ContentFilter {
+    db: Arc<dyn Database>,
+    /// Categories that may be served, `None` when every one may. Items of
+    /// categories arrbg doesn't know are hidden along with the denied ones,
+    /// nothing tells what they hold.
+    categories: Option<Vec<Category>>,
+    /// Titles matching any of these are never served
+    titles: Vec<Regex>,
+}
+
+/// Categories `config` lets through, `None` when it doesn't restrict them
+pub fn allowed_categories(config: &ContentFilterConfig) -> anyhow::Result<Option<Vec<Category>>> {
+    let parse = |terms: &[String]| -> anyhow::Result<Vec<Category>> {
+        let mut categories = Vec::new();
+        for term in terms {
+            categories.extend(term.parse::<CategoryList>()?.0);
+        }
+        Ok(categories)
+    };
+    let allowed = parse(&config.allow_categories)?;
+    let denied = parse(&config.deny_categories)?;
+    Ok((!allowed.is_empty() || !denied.is_empty()).then(|| {
+        Category::all()
+            .filter(|c| allowed.is_empty() || allowed.contains(c))
+            .filter(|c| !denied.contains(c))
+            .collect()
+    }))
+}
+
+impl ContentFilter {
+    pub fn new(db: Arc<dyn Database>, config: &ContentFilterConfig) -> anyhow::Result<Self> {
+        let categories = allowed_categories(config)?;
+        let titles = config
+            .deny_titles
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            db,
+            categories,
+            titles,
+        })
+    }
+
+    /// `request`, searching the allowed categories only
+    fn narrow(&self, request: &SearchRequest) -> SearchRequest {
+        let categories = match (&self.categories, &request.categories) {
+            (None, requested) => requested.clone(),
+            (Some(allowed), None) => Some(allowed.clone()),
+            (Some(allowed), Some(requested)) => Some(
+                requested
+                    .iter()
+                    .filter(|c| allowed.contains(c))
+                    .copied()
+                    .collect(),
+            ),
+        };
+        SearchRequest {
+            categories,
+            ..request.clone()
+        }
+    }
+
+    fn allows(&self, item: &Item) -> bool {
+        !self.titles.iter().any(|title| title.is_match(&item.title))
+    }
+}
+
+#[async_trait]
+impl Database for ContentFilter {
+    async fn search(&self, request: &SearchRequest) -> anyhow::Result<Vec<Item>> {
+        let results = self.search_sources(request).await?;
+        for failure in results.failures {
+            warn!("Source {} failed: {}", failure.source, failure.error);
+        }
+        Ok(results.items)
+    }
+
+    fn next_cursor(&self, request: &SearchRequest, page: &[Item]) -> Option<Cursor> {
+        self.db.next_cursor(&self.narrow(request), page)
+    }
+
+    async fn search_sources(&self, request: &SearchRequest) -> anyhow::Result<SearchResults> {
+        let request = self.narrow(request);
+        if request.categories.as_ref().is_some_and(Vec::is_empty) {
+            // Asked for denied categories only
+            return Ok(SearchResults::default());
+        }
+        if self.titles.is_empty() {
+            return self.db.search_sources(&request).await;
+        }
+
+        // Every page fails the same sources, the first page's are reported
+        let failures = Mutex::new(None);
+        let filtered = filter_pages(
+            request.pagination,
+            |item| self.allows(item),
+            |pagination| {
+                let request = SearchRequest {
+                    pagination,
+                    ..request.clone()
+                };
+                let failures = &failures;
+                async move {
+                    let results = self.db.search_sources(&request).await?;
+                    let mut failures = failures.lock().unwrap_or_else(|e| e.into_inner());
+                    failures.get_or_insert(results.failures);
+                    Ok(results.items)
+                }
+            },
+            |pagination, page| {
+                let request = SearchRequest {
+                    pagination,
+                    ..request.clone()
+                };
+                self.db.next_cursor(&request, page)
+            },
+        )
+        .await?;
+        let mut results = filtered.into_results("content_filter");
+        let failures = failures.into_inner().unwrap_or_else(|e| e.into_inner());
+        results.failures.extend(failures.unwrap_or_default());
+        Ok(results)
+    }
+
+    async fn imdb_id(&self, source: IdSource, external_id: &str) -> anyhow::Result<Option<String>> {
+        self.db.imdb_id(source, external_id).await
+    }
+
+    async fn store_id_mappings(&self, mappings: &[IdMapping]) -> anyhow::Result<u64> {
+        self.db.store_id_mappings(mappings).await
+    }
+
+    async fn record_hits(&self, items: &[Item]) -> anyhow::Result<()> {
+        self.db.record_hits(items).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ContentFilter;
+    use crate::config::ContentFilterConfig;
+    use crate::database::rarbg::category::Category;
+    use crate::database::rarbg::{self, RarbgTorrentSimple};
+    use crate::database::{Database, Pagination, SearchRequest};
+
+    const ITEMS: &str = r#"
+        INSERT INTO items (id, hash, title, dt, cat, imdb) VALUES
+            (1, '0101010101010101010101010101010101010101', 'Movie.2020.1080p.x264', '2020-01-01 00:00:01', 'movies_x264_1080', 'tt0000001'),
+            (2, '0202020202020202020202020202020202020202', 'Adult.Movie.2020.1080p', '2020-01-01 00:00:02', 'xxx', 'tt0000001'),
+            (3, '0303030303030303030303030303030303030303', 'Movie.2020.CAM.x264', '2020-01-01 00:00:03', 'movies_x264', 'tt0000001'),
+            (4, '0404040404040404040404040404040404040404', 'Show.S01E01.720p', '2020-01-01 00:00:04', 'tv', NULL),
+            (5, '0505050505050505050505050505050505050505', 'Some.Ebook.2020', '2020-01-01 00:00:05', 'ebooks', NULL);
+    "#;
+
+    async fn filtered(config: ContentFilterConfig) -> ContentFilter {
+        let db = rarbg::test_db(ITEMS).await;
+        ContentFilter::new(Arc::new(db), &config).unwrap()
+    }
+
+    async fn titles(db: &ContentFilter, request: SearchRequest) -> Vec<String> {
+        db.search(&request)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| RarbgTorrentSimple::from_item(item, &[]).unwrap().filename)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn never_serves_denied_content() {
+        let db = filtered(ContentFilterConfig {
+            deny_categories: vec!["xxx".to_string()],
+            deny_titles: vec![r"(?i)\bcam\b".to_string()],
+            ..Default::default()
+        })
+        .await;
+
+        // Listing, as with mode=list
+        assert_eq!(
+            titles(&db, SearchRequest::default()).await,
+            ["Show.S01E01.720p", "Movie.2020.1080p.x264"]
+        );
+        let search = SearchRequest {
+            text: "movie".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, search).await, ["Movie.2020.1080p.x264"]);
+        let by_imdb = SearchRequest {
+            imdb_id: Some("tt0000001".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(titles(&db, by_imdb).await, ["Movie.2020.1080p.x264"]);
+        // Not even when asked for by category
+        let asked = SearchRequest {
+            categories: Some(vec![Category::XXX, Category::MoviesX264]),
+            ..Default::default()
+        };
+        assert!(titles(&db, asked).await.is_empty());
+
+        // Pages are filled past the hidden items
+        let mut paged = SearchRequest {
+            pagination: Pagination {
+                amount: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let first = db.search(&paged).await.unwrap();
+        paged.pagination.after = db.next_cursor(&paged, &first);
+        assert_eq!(titles(&db, paged).await, ["Movie.2020.1080p.x264"]);
+    }
+
+    #[tokio::test]
+    async fn serves_allowed_categories_only() {
+        let db = filtered(ContentFilterConfig {
+            allow_categories: vec!["movies".to_string(), "41".to_string()],
+            deny_categories: vec!["movies_x264".to_string()],
+            ..Default::default()
+        })
+        .await;
+
+        assert_eq!(
+            titles(&db, SearchRequest::default()).await,
+            ["Show.S01E01.720p", "Movie.2020.1080p.x264"]
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! TVDB and TMDB ids mapped to IMDb ids. Stored locally, id searches keep
+//! working while Sonarr or Radarr are down.
+
+use anyhow::{bail, Context};
+
+use crate::tvdb::normalize_imdb_id;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdSource {
+    Tvdb,
+    /// TMDB movie ids, TV shows have their own id space there
+    Tmdb,
+}
+
+impl IdSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IdSource::Tvdb => "tvdb",
+            IdSource::Tmdb => "tmdb",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdMapping {
+    pub source: IdSource,
+    pub external_id: String,
+    pub imdb_id: String,
+}
+
+/// Reads a CSV mapping dump. The header names the columns: `imdb_id`, and
+/// `tvdb_id` and/or `tmdb_id`, in any order and among any others. Rows
+/// without a valid IMDb id are skipped.
+pub fn parse_id_mappings(csv: &str) -> anyhow::Result<Vec<IdMapping>> {
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+    let header = split_csv(lines.next().context("the mapping file is empty")?);
+    let column = |name: &str| header.iter().position(|c| c.eq_ignore_ascii_case(name));
+
+    let imdb = column("imdb_id").context("the mapping file has no imdb_id column")?;
+    let sources = [(IdSource::Tvdb, "tvdb_id"), (IdSource::Tmdb, "tmdb_id")]
+        .into_iter()
+        .filter_map(|(source, name)| Some((source, column(name)?)))
+        .collect::<Vec<_>>();
+    if sources.is_empty() {
+        bail!("the mapping file has neither a tvdb_id nor a tmdb_id column");
+    }
+
+    let mut mappings = Vec::new();
+    for line in lines {
+        let fields = split_csv(line);
+        let Some(imdb_id) = fields.get(imdb).and_then(|id| normalize_imdb_id(id)) else {
+            continue;
+        };
+        for &(source, i) in &sources {
+            if let Some(id) = fields.get(i).filter(|id| !id.is_empty()) {
+                mappings.push(IdMapping {
+                    source,
+                    external_id: id.to_string(),
+                    imdb_id: imdb_id.clone(),
+                });
+            }
+        }
+    }
+    Ok(mappings)
+}
+
+/// Just enough CSV for id dumps: quoted fields may contain commas
+fn split_csv(line: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                fields.push(&line[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&line[start..]);
+    fields
+        .into_iter()
+        .map(|field| field.trim().trim_matches('"'))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_id_mappings, IdMapping, IdSource};
+
+    const FIXTURE: &str = include_str!("../../fixtures/id_mappings.csv");
+
+    fn mapping(source: IdSource, external_id: &str, imdb_id: &str) -> IdMapping {
+        IdMapping {
+            source,
+            external_id: external_id.to_string(),
+            imdb_id: imdb_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_mapping_dumps() {
+        assert_eq!(
+            parse_id_mappings(FIXTURE).unwrap(),
+            [
+                mapping(IdSource::Tvdb, "81189", "tt0903747"),
+                mapping(IdSource::Tvdb, "73244", "tt0386676"),
+                mapping(IdSource::Tmdb, "146", "tt0190332"),
+                mapping(IdSource::Tmdb, "603", "tt0133093"),
+            ]
+        );
+
+        assert!(parse_id_mappings("").is_err());
+        assert!(parse_id_mappings("tvdb_id,tmdb_id\n1,2").is_err());
+        assert!(parse_id_mappings("imdb_id,title\ntt1,x").is_err());
+    }
+}
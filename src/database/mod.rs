@@ -1,9 +1,340 @@
+use std::fmt::{self, Debug, Display};
+use std::future::Future;
+
 use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::release::ReleaseFilter;
+
+use self::cursor::{Cursor, SortKey};
+use self::ids::{IdMapping, IdSource};
+use self::rarbg::category::Category;
 
+pub mod cursor;
+pub mod dump;
+pub mod federated;
+pub mod filter;
+pub mod ids;
 pub mod rarbg;
 
+pub const MAX_RESULTS: usize = 100;
+pub const DEFAULT_RESULTS: usize = 10;
+
+/// How many pages of results a filter the query can't apply may look
+/// through before settling for fewer results
+const MAX_FILTERED_PAGES: usize = 20;
+/// How deep searches that can only skip items, by counting them, may go
+const MAX_SKIPPED: usize = MAX_FILTERED_PAGES * MAX_RESULTS;
+
+/// A store of torrents the APIs search. The RARBG dump is one, other dumps
+/// and backends plug in by implementing this.
 #[async_trait]
-pub trait Database {
-    async fn search_tvdb(id: i64) -> anyhow::Result<String>;
-    async fn search_text(query: &str) -> anyhow::Result<String>;
+pub trait Database: Debug + Send + Sync {
+    async fn search(&self, request: &SearchRequest) -> anyhow::Result<Vec<Item>>;
+
+    /// Where the page after `page`, found for `request`, starts. `None` once
+    /// there are no more results. Backends that can't seek count items.
+    fn next_cursor(&self, request: &SearchRequest, page: &[Item]) -> Option<Cursor> {
+        let pagination = request.pagination;
+        (page.len() >= pagination.amount()).then(|| Cursor::Skip(pagination.skipped() + page.len()))
+    }
+
+    /// Like `search`, but backends made of several sources report the ones
+    /// that failed rather than failing as a whole
+    async fn search_sources(&self, request: &SearchRequest) -> anyhow::Result<SearchResults> {
+        Ok(SearchResults {
+            items: self.search(request).await?,
+            ..Default::default()
+        })
+    }
+
+    /// Locally known IMDb id of a TVDB/TMDB id. Backends without a place to
+    /// keep id mappings know none, so every lookup goes to Sonarr/Radarr.
+    async fn imdb_id(
+        &self,
+        _source: IdSource,
+        _external_id: &str,
+    ) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Adds id mappings and returns how many were stored
+    async fn store_id_mappings(&self, _mappings: &[IdMapping]) -> anyhow::Result<u64> {
+        Ok(0)
+    }
+
+    /// Counts one search hit for every item a client was sent, for the
+    /// swarm refresher to tell which items are wanted. Backends without
+    /// swarm stats don't count.
+    async fn record_hits(&self, _items: &[Item]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Everything a search can ask for
+#[derive(Debug, Default, Clone)]
+pub struct SearchRequest {
+    /// In the `search_string` language, see `rarbg::query`
+    pub text: String,
+    /// `None` searches every category
+    pub categories: Option<Vec<Category>>,
+    pub imdb_id: Option<String>,
+    pub pagination: Pagination,
+    pub sort: Sort,
+    pub min_seeders: Option<u32>,
+    /// What the release names must tell
+    pub release: ReleaseFilter,
+}
+
+/// What a search found, and which sources couldn't tell or gave up looking
+#[derive(Default)]
+pub struct SearchResults {
+    pub items: Vec<Item>,
+    pub failures: Vec<SourceFailure>,
+    /// Where to continue when the search gave up before the page was full,
+    /// see `filter_pages`. `next_cursor` can't tell from a short page.
+    pub resume: Option<Cursor>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceFailure {
+    pub source: String,
+    pub error: String,
+}
+
+/// A search result, shaped like a row of the RARBG dump
+#[derive(Clone, sqlx::FromRow, Serialize)]
+// NOTE: allow(dead_code) rationale: These fields are necessary for SQLX,
+// but may not be used in the code
+#[allow(dead_code)]
+pub struct Item {
+    id: i64,
+    title: String,
+    hash: String,
+    /// `%Y-%m-%d %H:%M:%S`, UTC
+    dt: String,
+    /// Category name, see `Category`
+    cat: String,
+    imdb: Option<String>,
+    size: Option<i64>,
+    ext_id: Option<String>,
+    /// From `swarm_stats`, NULL until the item was scraped
+    seeders: Option<i64>,
+    leechers: Option<i64>,
+    /// Full-text rank, only selected by ranked searches
+    #[sqlx(default)]
+    #[serde(skip)]
+    rank: Option<f64>,
+    /// Index of the source a federated search found the item in, ids are
+    /// only unique within one
+    #[sqlx(default)]
+    #[serde(skip)]
+    source: Option<u32>,
+}
+
+/// Searches failing through no fault of the backend. They travel as
+/// `anyhow::Error`s like any other failure and are told apart by
+/// downcasting.
+#[derive(Debug)]
+pub enum SearchError {
+    /// The request can't be answered as given, such as a cursor another
+    /// search handed out
+    InvalidRequest(String),
+    /// None of the sources of a federated search answered
+    Unavailable(Vec<SourceFailure>),
+}
+
+impl Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::InvalidRequest(e) => f.write_str(e),
+            SearchError::Unavailable(failures) => {
+                f.write_str("every source failed")?;
+                for (i, failure) in failures.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { ", " };
+                    write!(f, "{separator}{} ({})", failure.source, failure.error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+/// Which page of results to return. `after` comes first, then `offset`
+/// more items are skipped.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Pagination {
+    /// Costs a scan over every skipped item, unlike a cursor
+    pub offset: usize,
+    pub amount: Option<usize>,
+    /// `next_cursor` of the previous page
+    pub after: Option<Cursor>,
+}
+
+/// Result ordering, named after torrentapi's `sort` parameter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// Newest uploads first
+    #[default]
+    Last,
+    Seeders,
+    Leechers,
+    /// Best title matches first. Needs the full-text index, without it this
+    /// is the same as `Last`.
+    Relevance,
+}
+
+impl Pagination {
+    pub fn amount(&self) -> usize {
+        match self.amount {
+            Some(a) => a.clamp(1, MAX_RESULTS),
+            None => DEFAULT_RESULTS,
+        }
+    }
+
+    /// Items to skip: `offset`, plus the ones a `Skip` cursor passed over
+    pub fn skipped(&self) -> usize {
+        match self.after {
+            Some(Cursor::Skip(items)) => self.offset + items,
+            _ => self.offset,
+        }
+    }
+
+    /// The item to continue after, for backends that can seek
+    pub fn seek(&self) -> Option<(SortKey, i64)> {
+        match self.after {
+            Some(Cursor::After { key, id }) => Some((key, id)),
+            _ => None,
+        }
+    }
+
+    /// Fails on keyset cursors, for backends that can only skip items and
+    /// hand out `Skip` cursors
+    pub fn ensure_skipping(&self) -> anyhow::Result<()> {
+        if self.seek().is_some() {
+            return Err(SearchError::InvalidRequest(
+                "cursor wasn't handed out by this search".to_string(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// What `filter_pages` found
+struct Filtered {
+    items: Vec<Item>,
+    /// After the last item looked at, when the pages ran out before the
+    /// matches filled the page
+    resume: Option<Cursor>,
+    /// Why more may match, when the pages ran out and `resume` can't tell
+    /// where to continue
+    gave_up: Option<String>,
+}
+
+impl Filtered {
+    /// As the results of `source`. Giving up is reported as its failure, so
+    /// clients know results are missing rather than absent.
+    fn into_results(self, source: &str) -> SearchResults {
+        SearchResults {
+            items: self.items,
+            failures: self
+                .gave_up
+                .map(|error| SourceFailure {
+                    source: source.to_string(),
+                    error,
+                })
+                .into_iter()
+                .collect(),
+            resume: self.resume,
+        }
+    }
+}
+
+/// Fetches pages of results until `pagination` is filled with items passing
+/// `keep`. For filters the query can't apply, such as anything parsed from
+/// release names, which the query can only narrow down roughly beforehand.
+///
+/// Gives up after `MAX_FILTERED_PAGES`. Backends that can seek then resume
+/// after the last item looked at: `resume` gives their cursor after a page
+/// fetched for the given pagination. Others tell why in `gave_up`.
+async fn filter_pages<F, Fut>(
+    pagination: Pagination,
+    keep: impl Fn(&Item) -> bool,
+    mut fetch: F,
+    resume: impl Fn(Pagination, &[Item]) -> Option<Cursor>,
+) -> anyhow::Result<Filtered>
+where
+    F: FnMut(Pagination) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<Item>>>,
+{
+    let skipped = pagination.skipped();
+    let wanted = skipped + pagination.amount();
+    // Keyset cursors are followed by every page, skipped items are counted
+    // among the matches
+    let after = pagination
+        .after
+        .filter(|cursor| matches!(cursor, Cursor::After { .. }));
+    let mut matches = Vec::new();
+    let mut offset = 0;
+    // Only set while the pages ran out
+    let mut cut_short = None;
+    for _ in 0..MAX_FILTERED_PAGES {
+        let page_pagination = Pagination {
+            offset,
+            amount: Some(MAX_RESULTS),
+            after,
+        };
+        let page = fetch(page_pagination).await?;
+        let exhausted = page.len() < MAX_RESULTS;
+        offset += page.len();
+        let after_page = resume(page_pagination, &page);
+        matches.extend(page.into_iter().filter(&keep));
+        if exhausted || matches.len() >= wanted {
+            cut_short = None;
+            break;
+        }
+        cut_short = Some(after_page);
+    }
+
+    let mut gave_up = None;
+    let resume = cut_short.and_then(|after_page| {
+        warn!(
+            "Gave up filtering after {} results, {} of {} wanted matched",
+            offset,
+            matches.len(),
+            wanted
+        );
+        // Unless matches that were still to be skipped would be lost
+        let resume = after_page
+            .filter(|cursor| matches!(cursor, Cursor::After { .. }))
+            .filter(|_| matches.len() >= skipped);
+        if resume.is_none() {
+            gave_up = Some(format!(
+                "gave up after looking through {offset} results, more may match"
+            ));
+        }
+        resume
+    });
+    matches.truncate(wanted);
+    Ok(Filtered {
+        items: matches.split_off(skipped.min(matches.len())),
+        resume,
+        gave_up,
+    })
+}
+
+/// An in-memory database. Every connection to `:memory:` opens a database
+/// of its own, so there is only one.
+#[cfg(test)]
+pub async fn test_pool() -> sqlx::SqlitePool {
+    sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
 }
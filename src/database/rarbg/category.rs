@@ -6,7 +6,7 @@ use serde::de::Visitor;
 use serde::Deserialize;
 use tracing::debug;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Category {
     XXX = 4,
@@ -119,29 +119,11 @@ impl TryFrom<&str> for Category {
             return Self::try_from(i);
         };
         match value {
-            "xxx" => Ok(Category::XXX),
-            "movies_x264" => Ok(Category::MoviesX264),
-            "movies_x264_1080" => Ok(Category::MoviesX264_1080),
-            "movies_x264_720" => Ok(Category::MoviesX264_720),
-            "movies_x264_3d" => Ok(Category::MoviesX264_3d),
-            "movies_x264_4k" => Ok(Category::MoviesX264_4k),
-            "movies_x265_4k" => Ok(Category::MoviesX265_4k),
-            "movies_x265_4k_hdr" => Ok(Category::MoviesX265_4kHdr),
-            "movies_full_bd" => Ok(Category::MoviesFullBd),
-            "movies_bd_remux" => Ok(Category::MoviesBdRemux),
-            "movies_x265_1080" => Ok(Category::MoviesX265_1080),
-            "movies_xvid" => Ok(Category::MoviesXvid),
-            "movies_xvid_720" => Ok(Category::MoviesXvid720),
-            "tv" => Ok(Category::TvEpisodes),
-            "tv_sd" => Ok(Category::TvHdEpisodes),
-            "tv_uhd" => Ok(Category::TvUhdEpisodes),
-            "music_mp3" => Ok(Category::MusicMp3),
-            "music_flac" => Ok(Category::MusicFlac),
-            "games_pc_iso" => Ok(Category::GamesPcIso),
-            "games_pc_rip" => Ok(Category::GamesPcRip),
-            "software_pc_iso" => Ok(Category::SoftwarePcIso),
-            "games_ps4" => Ok(Category::GamesPs4),
-            _ => Err(anyhow::anyhow!("Invalid category: '{}'", value)),
+            // Clearer than the name the dump gave it
+            "tv_hd" => Ok(Category::TvHdEpisodes),
+            _ => Category::all()
+                .find(|c| c.name() == value)
+                .ok_or_else(|| anyhow::anyhow!("Invalid category: '{}'", value)),
         }
     }
 }
@@ -155,6 +137,135 @@ impl FromStr for Category {
 }
 
 impl Category {
+    const ALL: [Category; 22] = [
+        Category::XXX,
+        Category::MoviesX264,
+        Category::MoviesX264_1080,
+        Category::MoviesX264_720,
+        Category::MoviesX264_3d,
+        Category::MoviesX264_4k,
+        Category::MoviesX265_4k,
+        Category::MoviesX265_4kHdr,
+        Category::MoviesXvid,
+        Category::MoviesXvid720,
+        Category::MoviesFullBd,
+        Category::MoviesBdRemux,
+        Category::MoviesX265_1080,
+        Category::TvEpisodes,
+        Category::TvHdEpisodes,
+        Category::TvUhdEpisodes,
+        Category::MusicMp3,
+        Category::MusicFlac,
+        Category::GamesPcIso,
+        Category::GamesPcRip,
+        Category::SoftwarePcIso,
+        Category::GamesPs4,
+    ];
+
+    pub fn all() -> impl Iterator<Item = Category> {
+        Self::ALL.into_iter()
+    }
+
+    /// The numeric id torrentapi used
+    pub fn id(&self) -> u32 {
+        *self as u32
+    }
+
+    /// How torrentapi labelled the category in its results
+    pub fn label(&self) -> &'static str {
+        match self {
+            Category::XXX => "XXX (18+)",
+            Category::MoviesX264 => "Movies/x264",
+            Category::MoviesX264_1080 => "Movies/x264/1080",
+            Category::MoviesX264_720 => "Movies/x264/720",
+            Category::MoviesX264_3d => "Movies/x264/3D",
+            Category::MoviesX264_4k => "Movies/x264/4k",
+            Category::MoviesX265_4k => "Movies/x265/4k",
+            Category::MoviesX265_4kHdr => "Movies/x265/4k/HDR",
+            Category::MoviesFullBd => "Movies/Full BD",
+            Category::MoviesBdRemux => "Movies/BD Remux",
+            Category::MoviesX265_1080 => "Movies/x265/1080",
+            Category::MoviesXvid => "Movies/XVID",
+            Category::MoviesXvid720 => "Movies/XVID/720",
+            Category::TvEpisodes => "TV Episodes",
+            Category::TvHdEpisodes => "TV HD Episodes",
+            Category::TvUhdEpisodes => "TV UHD Episodes",
+            Category::MusicMp3 => "Music/MP3",
+            Category::MusicFlac => "Music/FLAC",
+            Category::GamesPcIso => "Games/PC ISO",
+            Category::GamesPcRip => "Games/PC RIP",
+            Category::SoftwarePcIso => "Software/PC ISO",
+            Category::GamesPs4 => "Games/PS4",
+        }
+    }
+
+    pub fn group(&self) -> CategoryGroup {
+        match self {
+            Category::XXX => CategoryGroup::Xxx,
+            Category::MoviesX264
+            | Category::MoviesX264_1080
+            | Category::MoviesX264_720
+            | Category::MoviesX264_3d
+            | Category::MoviesX264_4k
+            | Category::MoviesX265_4k
+            | Category::MoviesX265_4kHdr
+            | Category::MoviesFullBd
+            | Category::MoviesBdRemux
+            | Category::MoviesX265_1080
+            | Category::MoviesXvid
+            | Category::MoviesXvid720 => CategoryGroup::Movies,
+            Category::TvEpisodes | Category::TvHdEpisodes | Category::TvUhdEpisodes => {
+                CategoryGroup::Tv
+            }
+            Category::MusicMp3 | Category::MusicFlac => CategoryGroup::Music,
+            Category::GamesPcIso | Category::GamesPcRip | Category::GamesPs4 => {
+                CategoryGroup::Games
+            }
+            Category::SoftwarePcIso => CategoryGroup::Software,
+        }
+    }
+
+    pub fn is_4k(&self) -> bool {
+        matches!(
+            self,
+            Category::MoviesX264_4k
+                | Category::MoviesX265_4k
+                | Category::MoviesX265_4kHdr
+                | Category::TvUhdEpisodes
+        )
+    }
+
+    /// Closest Newznab/Torznab category, see
+    /// https://torznab.github.io/spec-1.3-draft/external/newznab/api.html#predefined-categories
+    pub fn torznab_id(&self) -> u32 {
+        match self {
+            Category::XXX => 6000,
+            Category::MoviesX264 | Category::MoviesXvid => 2030,
+            Category::MoviesX264_1080
+            | Category::MoviesX264_720
+            | Category::MoviesXvid720
+            | Category::MoviesX265_1080 => 2040,
+            Category::MoviesX264_4k | Category::MoviesX265_4k | Category::MoviesX265_4kHdr => 2045,
+            Category::MoviesFullBd | Category::MoviesBdRemux => 2050,
+            Category::MoviesX264_3d => 2060,
+            Category::TvEpisodes => 5030,
+            Category::TvHdEpisodes => 5040,
+            Category::TvUhdEpisodes => 5045,
+            Category::MusicMp3 => 3010,
+            Category::MusicFlac => 3040,
+            Category::GamesPcIso | Category::GamesPcRip => 4050,
+            Category::SoftwarePcIso => 4010,
+            Category::GamesPs4 => 1180,
+        }
+    }
+
+    /// The top level Torznab category, e.g. 2000 for every movie category
+    pub fn torznab_parent_id(&self) -> u32 {
+        self.torznab_id() / 1000 * 1000
+    }
+
+    /// Name of the category in the `cat` column of the dump. HD episodes
+    /// are plain `tv` there, SD ones `tv_sd`.
     pub fn name(&self) -> String {
         match self {
             Category::XXX => "xxx",
@@ -170,8 +281,8 @@ impl Category {
             Category::MoviesX265_1080 => "movies_x265_1080",
             Category::MoviesXvid => "movies_xvid",
             Category::MoviesXvid720 => "movies_xvid_720",
-            Category::TvEpisodes => "tv",
-            Category::TvHdEpisodes => "tv_sd",
+            Category::TvEpisodes => "tv_sd",
+            Category::TvHdEpisodes => "tv",
             Category::TvUhdEpisodes => "tv_uhd",
             Category::MusicMp3 => "music_mp3",
             Category::MusicFlac => "music_flac",
@@ -183,3 +294,149 @@ impl Category {
         .to_string()
     }
 }
+
+/// Categories of the same kind, which the `category` parameter takes by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CategoryGroup {
+    Movies,
+    Tv,
+    Music,
+    Games,
+    Software,
+    Xxx,
+}
+
+impl CategoryGroup {
+    const ALL: [CategoryGroup; 6] = [
+        CategoryGroup::Movies,
+        CategoryGroup::Tv,
+        CategoryGroup::Music,
+        CategoryGroup::Games,
+        CategoryGroup::Software,
+        CategoryGroup::Xxx,
+    ];
+
+    pub fn all() -> impl Iterator<Item = CategoryGroup> {
+        Self::ALL.into_iter()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CategoryGroup::Movies => "movies",
+            CategoryGroup::Tv => "tv",
+            CategoryGroup::Music => "music",
+            CategoryGroup::Games => "games",
+            CategoryGroup::Software => "software",
+            CategoryGroup::Xxx => "xxx",
+        }
+    }
+
+    pub fn categories(self) -> impl Iterator<Item = Category> {
+        Category::all().filter(move |c| c.group() == self)
+    }
+}
+
+impl FromStr for CategoryGroup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::all()
+            .find(|g| g.name() == s)
+            .ok_or_else(|| anyhow::anyhow!("Invalid category group: '{}'", s))
+    }
+}
+
+/// The `category` parameter: ids, names, groups and `4k`, separated by `;`.
+/// Groups take precedence, so `tv` is every TV category like it was for
+/// torrentapi, and `tv_hd` the dump's `tv` alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CategoryList(pub Vec<Category>);
+
+impl FromStr for CategoryList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut categories = Vec::new();
+        for term in s.split(';').map(str::trim).filter(|t| !t.is_empty()) {
+            let expanded = if let Ok(group) = term.parse::<CategoryGroup>() {
+                group.categories().collect()
+            } else if term.eq_ignore_ascii_case("4k") {
+                Category::all().filter(Category::is_4k).collect()
+            } else {
+                vec![term.parse()?]
+            };
+            for category in expanded {
+                if !categories.contains(&category) {
+                    categories.push(category);
+                }
+            }
+        }
+        Ok(CategoryList(categories))
+    }
+}
+
+impl Display for CategoryList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ids = self
+            .0
+            .iter()
+            .map(|c| c.id().to_string())
+            .collect::<Vec<_>>();
+        f.write_str(&ids.join(";"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Category, CategoryGroup, CategoryList};
+
+    #[test]
+    fn expands_category_lists() {
+        let list = |s: &str| s.parse::<CategoryList>().unwrap().0;
+
+        assert_eq!(
+            list("tv"),
+            [
+                Category::TvEpisodes,
+                Category::TvHdEpisodes,
+                Category::TvUhdEpisodes
+            ]
+        );
+        assert_eq!(
+            list("41;tv_hd;music_flac"),
+            [Category::TvHdEpisodes, Category::MusicFlac]
+        );
+        assert_eq!(
+            list("4k;movies_x265_4k"),
+            [
+                Category::MoviesX264_4k,
+                Category::MoviesX265_4k,
+                Category::MoviesX265_4kHdr,
+                Category::TvUhdEpisodes
+            ]
+        );
+        assert_eq!(list("xxx"), [Category::XXX]);
+        assert!(list("").is_empty());
+        assert!("movies;tv_4k".parse::<CategoryList>().is_err());
+
+        // Every category is in exactly one group
+        let grouped = CategoryGroup::all()
+            .map(|g| g.categories().count())
+            .sum::<usize>();
+        assert_eq!(grouped, Category::all().count());
+    }
+
+    #[test]
+    fn names_categories_consistently() {
+        for category in Category::all() {
+            assert_eq!(category.name().parse::<Category>().unwrap(), category);
+            assert_eq!(i64::from(category.id()).try_into().ok(), Some(category));
+        }
+        assert_eq!("tv".parse::<Category>().unwrap(), Category::TvHdEpisodes);
+        assert_eq!("tv_sd".parse::<Category>().unwrap(), Category::TvEpisodes);
+        assert_eq!(Category::MoviesX264_1080.id(), 44);
+        assert_eq!(Category::MoviesX264_1080.label(), "Movies/x264/1080");
+        assert_eq!(Category::MoviesX264_1080.torznab_id(), 2040);
+    }
+}
@@ -0,0 +1,253 @@
+//! Optional FTS5 index over `items.title`. Searching titles with GLOB scans
+//! the whole dump, so `arrbg index` builds this once and searches use it
+//! whenever it exists.
+
+use sqlx::QueryBuilder;
+use tracing::warn;
+
+use super::query::fts_tokenizer;
+use super::Database;
+
+/// Titles indexed per `INSERT`
+const INDEX_BATCH: i64 = 1000;
+
+impl Database {
+    /// (Re)builds `items_fts` from every title in the dump and returns the
+    /// number of indexed items. The index is contentless, it only maps words
+    /// to `items.id`, so it stays a fraction of the size of the dump.
+    ///
+    /// The tokenizer splits words like `query::is_separator` does. It only
+    /// folds ASCII case, like SQLite itself, so titles go in lowercased here.
+    pub async fn build_fts_index(&self) -> anyhow::Result<u64> {
+        let schema = self.side_schema;
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(&format!("DROP TABLE IF EXISTS {schema}.items_fts"))
+            .execute(&mut tx)
+            .await?;
+        sqlx::query(&format!(
+            "CREATE VIRTUAL TABLE {schema}.items_fts \
+             USING fts5(title, content='', tokenize=\"{}\")",
+            fts_tokenizer()
+        ))
+        .execute(&mut tx)
+        .await?;
+        let mut indexed = 0;
+        let mut after = i64::MIN;
+        loop {
+            let titles: Vec<(i64, String)> = sqlx::query_as(
+                "SELECT id, coalesce(title, '') FROM main.items WHERE id > ? ORDER BY id LIMIT ?",
+            )
+            .bind(after)
+            .bind(INDEX_BATCH)
+            .fetch_all(&mut tx)
+            .await?;
+            let Some(&(last, _)) = titles.last() else {
+                break;
+            };
+            after = last;
+
+            let mut query =
+                QueryBuilder::new(format!("INSERT INTO {schema}.items_fts (rowid, title) "));
+            query.push_values(titles, |mut row, (id, title)| {
+                row.push_bind(id).push_bind(title.to_lowercase());
+            });
+            indexed += query.build().execute(&mut tx).await?.rows_affected();
+        }
+        sqlx::query(&format!(
+            "INSERT INTO {schema}.items_fts (items_fts) VALUES ('optimize')"
+        ))
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(indexed)
+    }
+
+    pub(super) async fn has_fts_index(&self) -> anyhow::Result<bool> {
+        let index: Option<(String,)> = sqlx::query_as(&format!(
+            "SELECT sql FROM {}.sqlite_master WHERE type = 'table' AND name = 'items_fts'",
+            self.side_schema
+        ))
+        .fetch_optional(&self.db)
+        .await?;
+        let Some((sql,)) = index else {
+            return Ok(false);
+        };
+        if !sql.contains("tokenize") {
+            warn!("The full-text index splits words unlike searches without it, rebuild it with `arrbg index`");
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use crate::config::DatabaseConfig;
+    use crate::database::rarbg::{test_db, Database, RarbgTorrentSimple, TEST_ITEMS};
+    use crate::database::{Database as _, SearchRequest, Sort};
+
+    const ITEMS: &str = r#"
+        INSERT INTO items (id, hash, title, dt, cat) VALUES
+            (1, '0101010101010101010101010101010101010101', 'The.Matrix.1999.1080p.BluRay.x264', '', 'movies_x264_1080'),
+            (2, '0202020202020202020202020202020202020202', 'The.Matrix.Reloaded.2003.1080p.BluRay.x264', '', 'movies_x264_1080'),
+            (3, '0303030303030303030303030303030303030303', 'Matrix.Reloaded.Reloaded.2003.720p', '', 'movies_x264_720'),
+            (4, '0404040404040404040404040404040404040404', 'Reloaded.Behind.The.Scenes.2003', '', 'movies_xvid');
+    "#;
+
+    fn request(q: &str, sort: Sort) -> SearchRequest {
+        SearchRequest {
+            text: q.to_string(),
+            sort,
+            ..Default::default()
+        }
+    }
+
+    async fn ids(db: &Database, q: &str) -> Vec<i64> {
+        db.search(&request(q, Sort::Last))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| item.id)
+            .collect()
+    }
+
+    /// Ids of every result, fetched one page of `amount` at a time
+    async fn pages(db: &Database, q: &str, sort: Sort, amount: usize) -> Vec<i64> {
+        let mut request = request(q, sort);
+        request.pagination.amount = Some(amount);
+        let mut ids = Vec::new();
+        loop {
+            let page = db.search(&request).await.unwrap();
+            let next = db.next_cursor(&request, &page);
+            ids.extend(page.iter().map(|item| item.id));
+            match next {
+                Some(cursor) => request.pagination.after = Some(cursor),
+                None => return ids,
+            }
+        }
+    }
+
+    async fn titles(db: &Database, q: &str, sort: Sort) -> Vec<String> {
+        db.search(&request(q, sort))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|item| RarbgTorrentSimple::from_item(item, &[]).unwrap().filename)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn searches_with_and_without_index() {
+        let mut db = test_db(ITEMS).await;
+
+        // Both ways of searching must agree on what matches
+        let cases: &[(&str, &[i64])] = &[
+            ("", &[4, 3, 2, 1]),
+            ("matrix reloaded", &[3, 2]),
+            ("The.Matrix", &[2, 1]),
+            ("\"reloaded matrix\"", &[]),
+            ("reload", &[]),
+            ("reload*", &[4, 3, 2]),
+            ("matrix -reloaded", &[1]),
+            ("720p|behind", &[4, 3]),
+            ("-\"the matrix\" -720p", &[4]),
+        ];
+        for indexed in [false, true] {
+            if indexed {
+                assert_eq!(db.build_fts_index().await.unwrap(), 4);
+                db.fts = db.has_fts_index().await.unwrap();
+                assert!(db.fts);
+            }
+            for (q, expected) in cases {
+                assert_eq!(&ids(&db, q).await, expected, "{q} (indexed: {indexed})");
+            }
+            for sort in [Sort::Last, Sort::Relevance] {
+                for q in ["", "reloaded", "reloaded 2003 -behind"] {
+                    let all = db.search(&request(q, sort)).await.unwrap();
+                    let all = all.iter().map(|item| item.id).collect::<Vec<_>>();
+                    assert_eq!(pages(&db, q, sort, 1).await, all, "{q} by {sort:?}");
+                    assert_eq!(pages(&db, q, sort, 3).await, all, "{q} by {sort:?}");
+                }
+            }
+        }
+
+        assert_eq!(
+            titles(&db, "reloaded", Sort::Relevance).await[0],
+            "Matrix.Reloaded.Reloaded.2003.720p"
+        );
+        assert_eq!(titles(&db, "", Sort::Relevance).await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn splits_and_folds_titles_the_same_with_and_without_index() {
+        let mut db = test_db(
+            r#"
+            INSERT INTO items (id, hash, title, dt, cat) VALUES
+                (1, '0101010101010101010101010101010101010101', 'Marvels.Agents.of.S.H.I.E.L.D.S01E01', '', 'tv'),
+                (2, '0202020202020202020202020202020202020202', 'Marvel''s.Agents.of.S.H.I.E.L.D.S01E02', '', 'tv'),
+                (3, '0303030303030303030303030303030303030303', 'ÉLITE.S01E01.720p', '', 'tv'),
+                (4, '0404040404040404040404040404040404040404', 'élite: Season 2 [1080p]', '', 'tv'),
+                (5, '0505050505050505050505050505050505050505', 'Show: Title (2020)', '', 'movies'),
+                (6, '0606060606060606060606060606060606060606', 'Learn.C#.In.24.Hours', '', 'ebooks');
+            "#,
+        )
+        .await;
+
+        let cases: &[(&str, &[i64])] = &[
+            ("marvel's", &[2]),
+            ("marvels", &[1]),
+            ("s.h.i.e.l.d", &[2, 1]),
+            ("Élite", &[4, 3]),
+            ("ÉLITE season", &[4]),
+            ("\"élite season 2\"", &[4]),
+            ("élit*", &[4, 3]),
+            ("\"show: title\"", &[5]),
+            ("c#", &[6]),
+            ("c", &[]),
+            ("-élite -marvel*", &[6, 5]),
+        ];
+        for indexed in [false, true] {
+            if indexed {
+                db.build_fts_index().await.unwrap();
+                db.fts = db.has_fts_index().await.unwrap();
+            }
+            for (q, expected) in cases {
+                assert_eq!(&ids(&db, q).await, expected, "{q} (indexed: {indexed})");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_dump_pristine() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump = dir.path().join("dump.sqlite");
+        let url = format!("sqlite:{}?mode=rwc", dump.display());
+        let pool = SqlitePool::connect(&url).await.unwrap();
+        sqlx::query(TEST_ITEMS).execute(&pool).await.unwrap();
+        sqlx::query(ITEMS).execute(&pool).await.unwrap();
+        pool.close().await;
+
+        let config = DatabaseConfig {
+            url: url.clone(),
+            sidecar: Some(dir.path().join("sidecar.sqlite")),
+        };
+        let db = Database::connect(&config).await.unwrap();
+        assert!(!db.fts);
+        db.build_fts_index().await.unwrap();
+
+        let db = Database::connect(&config).await.unwrap();
+        assert!(db.fts);
+        assert_eq!(titles(&db, "behind", Sort::Last).await.len(), 1);
+
+        let pool = SqlitePool::connect(&url).await.unwrap();
+        let tables: Vec<(String,)> =
+            sqlx::query_as("SELECT name FROM sqlite_master WHERE type = 'table'")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(tables, [("items".to_string(),)]);
+    }
+}
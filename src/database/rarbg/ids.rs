@@ -0,0 +1,113 @@
+//! The `id_mappings` table, TVDB and TMDB ids mapped to IMDb ids, the only
+//! id the dump knows. Seeded with `arrbg import-ids`, and every answer
+//! Sonarr and Radarr give is added.
+
+use sqlx::QueryBuilder;
+
+use crate::database::ids::{IdMapping, IdSource};
+
+use super::Database;
+
+/// SQLite allows 999 bound parameters per statement, mappings take three
+const STORE_BATCH: usize = 300;
+
+impl Database {
+    pub(super) async fn create_id_mappings(&self) -> anyhow::Result<()> {
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {}.id_mappings (
+                -- 'tvdb' or 'tmdb'
+                source TEXT NOT NULL,
+                external_id TEXT NOT NULL,
+                imdb_id TEXT NOT NULL,
+                PRIMARY KEY (source, external_id)
+            ) WITHOUT ROWID"#,
+            self.side_schema
+        ))
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_id_mapping(
+        &self,
+        source: IdSource,
+        external_id: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let imdb_id: Option<(String,)> = sqlx::query_as(
+            "SELECT imdb_id FROM id_mappings WHERE source = ?1 AND external_id = ?2",
+        )
+        .bind(source.as_str())
+        .bind(external_id.trim())
+        .fetch_optional(&self.db)
+        .await?;
+        Ok(imdb_id.map(|(imdb_id,)| imdb_id))
+    }
+
+    /// Adds the mappings, replacing those already known for the same ids
+    pub async fn insert_id_mappings(&self, mappings: &[IdMapping]) -> anyhow::Result<u64> {
+        let mut tx = self.db.begin().await?;
+        let mut stored = 0;
+        for batch in mappings.chunks(STORE_BATCH) {
+            let mut query =
+                QueryBuilder::new("INSERT INTO id_mappings (source, external_id, imdb_id) ");
+            query.push_values(batch, |mut row, mapping| {
+                row.push_bind(mapping.source.as_str())
+                    .push_bind(mapping.external_id.trim())
+                    .push_bind(&mapping.imdb_id);
+            });
+            query.push(
+                " ON CONFLICT (source, external_id) DO UPDATE SET imdb_id = excluded.imdb_id",
+            );
+            stored += query.build().execute(&mut tx).await?.rows_affected();
+        }
+        tx.commit().await?;
+        Ok(stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::ids::{parse_id_mappings, IdMapping, IdSource};
+    use crate::database::rarbg::test_db;
+
+    fn mapping(source: IdSource, external_id: &str, imdb_id: &str) -> IdMapping {
+        IdMapping {
+            source,
+            external_id: external_id.to_string(),
+            imdb_id: imdb_id.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_mappings() {
+        let db = test_db("").await;
+
+        let mappings =
+            parse_id_mappings(include_str!("../../../fixtures/id_mappings.csv")).unwrap();
+        assert_eq!(db.insert_id_mappings(&mappings).await.unwrap(), 4);
+        assert_eq!(
+            db.find_id_mapping(IdSource::Tvdb, "73244")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("tt0386676")
+        );
+        // The same number means something else on TMDB
+        assert_eq!(
+            db.find_id_mapping(IdSource::Tmdb, "73244").await.unwrap(),
+            None
+        );
+
+        db.insert_id_mappings(&[mapping(IdSource::Tmdb, "603", "tt0000603")])
+            .await
+            .unwrap();
+        assert_eq!(
+            db.find_id_mapping(IdSource::Tmdb, "603")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("tt0000603")
+        );
+    }
+}
@@ -1,28 +1,88 @@
-use std::cmp::max;
-
+use axum::async_trait;
+use chrono::{DateTime, NaiveDateTime};
 use serde::Serialize;
-use sqlx::SqlitePool;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+use tracing::debug;
+
+use crate::config::{DatabaseConfig, PRIMARY_SOURCE};
+use crate::release::Release;
+use crate::torrent::magnet::MagnetLink;
+
+use self::query::SearchQuery;
+use super::cursor::{Cursor, SortKey};
+use super::ids::{IdMapping, IdSource};
+use super::{filter_pages, Item, Pagination, SearchError, SearchRequest, SearchResults, Sort};
 
 pub mod category;
+pub mod fts;
+pub mod ids;
+pub mod query;
+pub mod swarm;
+
+/// Name the sidecar database is attached under
+const SIDECAR_SCHEMA: &str = "side";
 
-const MAX_RESULTS: usize = 100;
+/// Items along with the swarm counts collected for them, if any
+const SWARM_JOIN: &str = "items LEFT JOIN swarm_stats ON swarm_stats.item_id = items.id";
 
 #[derive(Debug, Clone)]
 pub struct Database {
     db: SqlitePool,
+    /// Schema holding the tables arrbg adds next to the dump
+    side_schema: &'static str,
+    /// Whether the `items_fts` full-text index was built
+    fts: bool,
 }
 
+/// Date format used by torrentapi's `pubdate` field
+const PUBDATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S +0000";
+/// Date format of the `dt` column in the dump
+const DUMP_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+/// RFC 822 date, as RSS wants it
+const RSS_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S +0000";
+
+/// A result as returned by `format=json`
+#[derive(Debug, Serialize)]
+pub struct RarbgTorrentSimple {
+    pub filename: String,
+    /// The label, or the dump's name for categories torrentapi didn't have
+    pub category: String,
+    pub download: String,
+}
+
+/// A result as returned by `format=json_extended`
 #[derive(Debug, Serialize)]
 pub struct RarbgTorrent {
     pub title: String,
-    pub category: String,
+    /// The numeric id
+    pub category: Option<u32>,
     pub download: String,
     pub seeders: Option<i64>,
     pub leechers: Option<i64>,
-    pub publish_date: String,
+    pub size: Option<i64>,
+    pub pubdate: String,
     pub episode_info: RarbgTorrentInfo,
     pub ranked: Option<i64>,
     pub info_page: String,
+    /// What the title tells about the release
+    pub release: Release,
+}
+
+/// A result as returned by the Torznab endpoint
+#[derive(Debug)]
+pub struct TorznabTorrent {
+    pub title: String,
+    pub info_hash: String,
+    pub download: String,
+    pub info_page: String,
+    pub pubdate: Option<String>,
+    pub size: Option<i64>,
+    pub category: Option<category::Category>,
+    pub seeders: Option<i64>,
+    pub leechers: Option<i64>,
+    pub imdb: Option<String>,
+    pub release: Release,
 }
 
 #[derive(Debug, Serialize)]
@@ -30,125 +90,440 @@ pub struct RarbgTorrentInfo {
     pub imdb: String,
     pub tvrage: Option<i64>,
     pub tvdb: Option<i64>,
+    pub themoviedb: Option<i64>,
+    /// Parsed from the title, torrentapi sent numbers as strings
+    pub seasonnum: Option<String>,
+    pub epnum: Option<String>,
+}
+
+impl RarbgTorrentSimple {
+    pub fn from_item(value: Item, trackers: &[String]) -> anyhow::Result<Self> {
+        Ok(RarbgTorrentSimple {
+            download: value.magnet(trackers)?.to_string(),
+            category: value
+                .category()
+                .map_or_else(|| value.cat.clone(), |c| c.label().to_string()),
+            filename: value.title,
+        })
+    }
 }
 
-impl From<Item> for RarbgTorrent {
-    fn from(value: Item) -> Self {
-        RarbgTorrent {
+impl RarbgTorrent {
+    pub fn from_item(value: Item, trackers: &[String]) -> anyhow::Result<Self> {
+        let release = value.release();
+        Ok(RarbgTorrent {
+            download: value.magnet(trackers)?.to_string(),
+            category: value.category().map(|c| c.id()),
+            pubdate: value.pubdate(),
+            info_page: value
+                .ext_id
+                .map(|id| format!("https://rarbg.to/torrent/{id}"))
+                .unwrap_or_default(),
             title: value.title,
-            category: value.cat,
-            download: value.hash,
-            seeders: None,
-            leechers: None,
-            publish_date: value.dt,
+            seeders: value.seeders,
+            leechers: value.leechers,
+            size: value.size,
             episode_info: RarbgTorrentInfo {
                 imdb: value.imdb.unwrap_or_default(),
                 tvrage: None,
                 tvdb: None,
+                themoviedb: None,
+                seasonnum: release.seasons.first().map(u16::to_string),
+                epnum: release.episodes.first().map(u16::to_string),
             },
             ranked: None,
-            info_page: "".to_string(),
-        }
+            release,
+        })
     }
 }
 
-#[derive(sqlx::FromRow, Serialize)]
-// NOTE: allow(dead_code) rationale: These fields are necessary for SQLX,
-// but may not be used in the code
-#[allow(dead_code)]
-pub struct Item {
-    id: i64,
-    title: String,
-    hash: String,
-    dt: String,
-    cat: String,
-    imdb: Option<String>,
-    size: Option<i64>,
-    ext_id: Option<String>,
+impl TorznabTorrent {
+    pub fn from_item(value: Item, trackers: &[String]) -> anyhow::Result<Self> {
+        Ok(TorznabTorrent {
+            download: value.magnet(trackers)?.to_string(),
+            category: value.category(),
+            release: value.release(),
+            pubdate: value
+                .published()
+                .map(|dt| dt.format(RSS_DATE_FORMAT).to_string()),
+            info_page: value
+                .ext_id
+                .map(|id| format!("https://rarbg.to/torrent/{id}"))
+                .unwrap_or_default(),
+            info_hash: value.hash.trim().to_lowercase(),
+            title: value.title,
+            size: value.size,
+            seeders: value.seeders,
+            leechers: value.leechers,
+            imdb: value.imdb.filter(|imdb| !imdb.is_empty()),
+        })
+    }
 }
 
-#[derive(Debug, Default)]
-pub struct Pagination {
-    pub offset: usize,
-    pub amount: Option<usize>,
+impl Item {
+    fn magnet(&self, trackers: &[String]) -> anyhow::Result<MagnetLink> {
+        Ok(MagnetLink::new(&self.hash)?
+            .display_name(&self.title)
+            .trackers(trackers))
+    }
+
+    pub fn release(&self) -> Release {
+        Release::parse(&self.title)
+    }
+
+    fn category(&self) -> Option<category::Category> {
+        self.cat.parse().ok()
+    }
+
+    /// `dt` as a UTC date. Other sources may write it with fractions of a
+    /// second, a `T` or an offset, so their results sort among the dump's.
+    pub(super) fn published(&self) -> Option<NaiveDateTime> {
+        let dt = self.dt.trim();
+        [DUMP_DATE_FORMAT, "%Y-%m-%dT%H:%M:%S"]
+            .into_iter()
+            .find_map(|format| {
+                // Fractions of a second and the offset are optional
+                let format = format!("{format}%.f");
+                let offset = DateTime::parse_from_str(dt, &format!("{format}%#z"));
+                offset
+                    .map(|dt| dt.naive_utc())
+                    .or_else(|_| NaiveDateTime::parse_from_str(dt, &format))
+                    .ok()
+            })
+    }
+
+    /// `dt` reformatted the way torrentapi reported publish dates. Dates the
+    /// dump stores in another shape are passed through untouched.
+    fn pubdate(&self) -> String {
+        self.published()
+            .map(|dt| dt.format(PUBDATE_FORMAT).to_string())
+            .unwrap_or_else(|| self.dt.clone())
+    }
+}
+
+/// Everything a search narrows the dump down by
+struct Filters<'a> {
+    search: &'a SearchQuery,
+    categories: Option<&'a [category::Category]>,
+    imdb_id: Option<&'a str>,
+    min_seeders: Option<u32>,
 }
 
-impl Pagination {
-    fn amount(&self) -> usize {
-        match self.amount {
-            Some(a) => max(a, MAX_RESULTS),
-            None => 10,
+impl Sort {
+    /// `ranked` tells whether the query exposes the full-text `rank` column
+    fn order_by(&self, ranked: bool) -> &'static str {
+        match self {
+            Sort::Last => "id DESC",
+            Sort::Relevance if ranked => "rank, id DESC",
+            Sort::Relevance => "id DESC",
+            // Items that were never scraped go last
+            Sort::Seeders => "seeders DESC NULLS LAST, id DESC",
+            Sort::Leechers => "leechers DESC NULLS LAST, id DESC",
         }
     }
 }
 
 impl Database {
     pub fn new(db: SqlitePool) -> Self {
-        Self { db }
+        Self {
+            db,
+            side_schema: "main",
+            fts: false,
+        }
     }
 
-    pub async fn search_query(
+    /// Opens the dump and creates the side tables, in the sidecar database if
+    /// one is configured.
+    pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<Self> {
+        let mut options = SqlitePoolOptions::new();
+        if let Some(sidecar) = &config.sidecar {
+            let sidecar = sidecar.to_string_lossy().into_owned();
+            options = options.after_connect(move |conn, _| {
+                let sidecar = sidecar.clone();
+                Box::pin(async move {
+                    sqlx::query(&format!("ATTACH DATABASE ?1 AS {SIDECAR_SCHEMA}"))
+                        .bind(sidecar)
+                        .execute(conn)
+                        .await?;
+                    Ok(())
+                })
+            });
+        }
+
+        let mut db = Self::new(options.connect(&config.url).await?);
+        if config.sidecar.is_some() {
+            db.side_schema = SIDECAR_SCHEMA;
+        }
+        db.migrate().await?;
+        db.fts = db.has_fts_index().await?;
+        if !db.fts {
+            debug!("No full-text index, searching titles with GLOB");
+        }
+        Ok(db)
+    }
+
+    /// Creates the side tables next to the dump, or in the sidecar database.
+    /// Safe to call on every start.
+    pub async fn migrate(&self) -> anyhow::Result<()> {
+        self.create_swarm_stats().await?;
+        self.create_id_mappings().await?;
+        Ok(())
+    }
+
+    /// One page of the items matching `filters`, ignoring the release filter
+    async fn search_page(
         &self,
-        q: &str,
-        categories: Option<&[category::Category]>,
-        imdb_id: Option<String>,
-        pagination: Option<Pagination>,
+        filters: &Filters<'_>,
+        sort: Sort,
+        pagination: Pagination,
     ) -> anyhow::Result<Vec<Item>> {
-        let title_query = format!("%{q}%");
-        let pagination = pagination.unwrap_or_default();
-        let amount = pagination.amount();
-        let mut category_params_start: usize = 4;
-
-        let imdb_query = if imdb_id.is_some() {
-            category_params_start += 1;
-            "AND imdb = ?4"
-        } else {
-            ""
-        };
+        let ranked = self.ranked(filters.search);
+        let mut query = QueryBuilder::new("SELECT items.*, seeders, leechers");
+        if ranked {
+            query.push(", rank");
+        }
+        query.push(" FROM ");
+        self.push_from(&mut query, filters);
+        if let Some((key, id)) = pagination.seek() {
+            push_after(&mut query, sort, ranked, key, id)?;
+        }
+        query.push(format!(" ORDER BY {} LIMIT ", sort.order_by(ranked)));
+        query.push_bind(pagination.amount() as u32);
+        query
+            .push(" OFFSET ")
+            .push_bind(pagination.skipped() as u32);
 
-        let category_names =
-            categories.map(|categories| categories.iter().map(|c| c.name()).collect::<Vec<_>>());
-        let category_query = if let Some(categories) = categories {
-            let category_params = categories
-                .iter()
-                .enumerate()
-                .map(|(i, _)| format!("?{}", i + category_params_start))
-                .collect::<Vec<_>>()
-                .join(",");
-            format!("AND cat IN ({})", category_params)
-        } else {
-            String::default()
-        };
+        Ok(query.build_query_as().fetch_all(&self.db).await?)
+    }
 
-        let query_str = format!(
-            r#"
-            SELECT * FROM items
-            WHERE title like ?1
-              {imdb_query}
-              {category_query}
-              AND id NOT IN (
-                SELECT id FROM items
-                WHERE title LIKE ?1
-                  {imdb_query}
-                  {category_query}
-                ORDER BY id DESC LIMIT ?3
-              )
-            ORDER BY id DESC LIMIT ?2"#
-        );
+    /// Whether searching for `search` orders by the full-text rank
+    fn ranked(&self, search: &SearchQuery) -> bool {
+        self.fts && search.ranks()
+    }
 
-        let mut db_query = sqlx::query_as(&query_str);
-        db_query = db_query.bind(title_query); // ?1
-        db_query = db_query.bind(amount as u32); // ?2
-        db_query = db_query.bind(pagination.offset as u32); // ?3
-        if let Some(imdb_id) = imdb_id {
-            db_query = db_query.bind(imdb_id); // ?4
+    /// The source and `WHERE` clause of a search, up to `filters`. With the
+    /// full-text index, the source is joined with its matches so they can be
+    /// ranked.
+    fn push_from<'a>(&self, query: &mut QueryBuilder<'a, Sqlite>, filters: &Filters) {
+        query.push(SWARM_JOIN);
+        let fts_schema = self.fts.then_some(self.side_schema);
+        if let (Some(schema), Some(fts_match)) = (fts_schema, filters.search.fts_match()) {
+            query.push(format!(
+                " JOIN (SELECT rowid AS fts_id, rank FROM {schema}.items_fts WHERE items_fts MATCH "
+            ));
+            query.push_bind(fts_match);
+            query.push(") ON fts_id = items.id");
         }
-        if let Some(categories) = category_names {
+
+        query.push(" WHERE TRUE");
+        filters.search.push_conditions(query, fts_schema);
+        if let Some(imdb_id) = filters.imdb_id {
+            query.push(" AND imdb = ").push_bind(imdb_id.to_string());
+        }
+        if let Some(min_seeders) = filters.min_seeders {
+            query.push(" AND seeders >= ").push_bind(min_seeders);
+        }
+        if let Some(categories) = filters.categories {
+            query.push(" AND cat IN (");
+            let mut names = query.separated(", ");
             for category in categories {
-                db_query = db_query.bind(category);
+                names.push_bind(category.name());
             }
+            names.push_unseparated(")");
+        }
+    }
+}
+
+/// Narrows a search down to the items sorting after `key` and `id`, as
+/// ordered by `Sort::order_by`
+fn push_after(
+    query: &mut QueryBuilder<'_, Sqlite>,
+    sort: Sort,
+    ranked: bool,
+    key: SortKey,
+    id: i64,
+) -> anyhow::Result<()> {
+    if key.sort() != sort || matches!(key, SortKey::Relevance(rank) if rank.is_some() != ranked) {
+        return Err(SearchError::InvalidRequest(
+            "cursor wasn't handed out by this search".to_string(),
+        )
+        .into());
+    }
+
+    match key {
+        SortKey::Last | SortKey::Relevance(None) => {
+            query.push(" AND id < ").push_bind(id);
+        }
+        SortKey::Seeders(Some(count)) | SortKey::Leechers(Some(count)) => {
+            let column = if sort == Sort::Seeders {
+                "seeders"
+            } else {
+                "leechers"
+            };
+            query.push(format!(" AND ({column} < "));
+            query.push_bind(count);
+            query.push(format!(" OR {column} IS NULL OR ({column} = "));
+            query.push_bind(count).push(" AND id < ").push_bind(id);
+            query.push("))");
+        }
+        SortKey::Seeders(None) => {
+            query.push(" AND seeders IS NULL AND id < ").push_bind(id);
+        }
+        SortKey::Leechers(None) => {
+            query.push(" AND leechers IS NULL AND id < ").push_bind(id);
+        }
+        SortKey::Relevance(Some(rank)) => {
+            query.push(" AND (rank > ").push_bind(rank);
+            query.push(" OR (rank = ").push_bind(rank);
+            query.push(" AND id < ").push_bind(id).push("))");
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl super::Database for Database {
+    async fn search(&self, request: &SearchRequest) -> anyhow::Result<Vec<Item>> {
+        Ok(self.search_sources(request).await?.items)
+    }
+
+    /// Release filtered searches that gave up before the page was full
+    /// resume after the last item they looked at
+    async fn search_sources(&self, request: &SearchRequest) -> anyhow::Result<SearchResults> {
+        let filter = &request.release;
+        let mut text = request.text.clone();
+        if !filter.is_empty() {
+            text.push(' ');
+            text.push_str(&filter.search_terms());
+        }
+        let search = SearchQuery::parse(&text);
+        let filters = Filters {
+            search: &search,
+            categories: request.categories.as_deref(),
+            imdb_id: request.imdb_id.as_deref(),
+            min_seeders: request.min_seeders,
+        };
+        let pagination = request.pagination;
+        if filter.is_empty() {
+            return Ok(SearchResults {
+                items: self.search_page(&filters, request.sort, pagination).await?,
+                ..Default::default()
+            });
         }
 
-        Ok(db_query.fetch_all(&self.db).await?)
+        let filtered = filter_pages(
+            pagination,
+            |item| filter.matches(&item.release()),
+            |pagination| self.search_page(&filters, request.sort, pagination),
+            |pagination, page| {
+                let request = SearchRequest {
+                    pagination,
+                    ..request.clone()
+                };
+                self.next_cursor(&request, page)
+            },
+        )
+        .await?;
+        Ok(filtered.into_results(PRIMARY_SOURCE))
+    }
+
+    async fn record_hits(&self, items: &[Item]) -> anyhow::Result<()> {
+        let ids = items.iter().map(|item| item.id).collect::<Vec<_>>();
+        self.add_hits(&ids).await
+    }
+
+    /// Continues after the last item, however deep the page
+    fn next_cursor(&self, request: &SearchRequest, page: &[Item]) -> Option<Cursor> {
+        let last = page
+            .last()
+            .filter(|_| page.len() >= request.pagination.amount())?;
+        let key = match request.sort {
+            Sort::Last => SortKey::Last,
+            Sort::Seeders => SortKey::Seeders(last.seeders),
+            Sort::Leechers => SortKey::Leechers(last.leechers),
+            Sort::Relevance => SortKey::Relevance(last.rank),
+        };
+        Some(Cursor::After { key, id: last.id })
+    }
+
+    async fn imdb_id(&self, source: IdSource, external_id: &str) -> anyhow::Result<Option<String>> {
+        self.find_id_mapping(source, external_id).await
+    }
+
+    async fn store_id_mappings(&self, mappings: &[IdMapping]) -> anyhow::Result<u64> {
+        self.insert_id_mappings(mappings).await
+    }
+}
+
+/// The dump's own table, as tests recreate it
+#[cfg(test)]
+pub const TEST_ITEMS: &str = "CREATE TABLE items (
+    id INTEGER PRIMARY KEY, hash TEXT, title TEXT, dt TEXT,
+    cat TEXT, size INTEGER, ext_id TEXT, imdb TEXT
+)";
+
+/// An in-memory dump, with the side tables created. `rows` is SQL run once
+/// the `items` table exists, to fill it.
+#[cfg(test)]
+pub async fn test_db(rows: &str) -> Database {
+    let pool = super::test_pool().await;
+    sqlx::query(TEST_ITEMS).execute(&pool).await.unwrap();
+    if !rows.trim().is_empty() {
+        sqlx::query(rows).execute(&pool).await.unwrap();
+    }
+    let db = Database::new(pool);
+    db.migrate().await.unwrap();
+    db
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_db;
+    use crate::database::cursor::{Cursor, SortKey};
+    use crate::database::{Database as _, SearchRequest};
+    use crate::release::ReleaseFilter;
+
+    #[tokio::test]
+    async fn resumes_filtered_searches_that_gave_up() {
+        // The wanted episode is the oldest, behind more than the filtered
+        // pages look through
+        let db = test_db(
+            r#"
+            INSERT INTO items (id, hash, title, dt, cat) VALUES
+                (1, '0101010101010101010101010101010101010101', 'Show.S01E05.720p', '', 'tv');
+            WITH RECURSIVE n(id) AS (SELECT 2 UNION ALL SELECT id + 1 FROM n WHERE id < 2101)
+            INSERT INTO items (id, hash, title, dt, cat)
+                SELECT id, printf('%040d', id), 'Show.S01E01.720p', '', 'tv' FROM n;
+            "#,
+        )
+        .await;
+
+        let mut request = SearchRequest {
+            release: ReleaseFilter {
+                season: Some(1),
+                episode: Some(5),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let results = db.search_sources(&request).await.unwrap();
+        assert!(results.items.is_empty());
+        assert_eq!(
+            results.resume,
+            Some(Cursor::After {
+                key: SortKey::Last,
+                id: 102
+            })
+        );
+
+        request.pagination.after = results.resume;
+        let results = db.search_sources(&request).await.unwrap();
+        let titles = results
+            .items
+            .into_iter()
+            .map(|item| item.title)
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["Show.S01E05.720p"]);
+        assert_eq!(results.resume, None);
     }
 }
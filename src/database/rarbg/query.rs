@@ -0,0 +1,350 @@
+//! The `search_string` language:
+//!
+//! - `the office`: every word must appear in the title, in any order
+//! - `"the office"`: the words must appear next to each other
+//! - `-us`, `-"the office"`: the title must not contain the word/phrase
+//! - `720p|1080p`: any of the alternatives
+//! - `off*`: any word starting with `off`
+//!
+//! Punctuation separates words just like spaces do, so `The.Office` is the
+//! same as `"the office"` and `Marvel's` as `"marvel s"`. Case doesn't
+//! matter, for non-ASCII letters either. Everything compiles to bound
+//! parameters, nothing in `search_string` ends up in the SQL itself.
+//!
+//! The full-text index and the `GLOB` fallback must find the same items, so
+//! both split words on `SEPARATORS`: the index through its tokenizer, the
+//! fallback through `normalized_title`.
+
+use sqlx::{QueryBuilder, Sqlite};
+
+/// Punctuation separating words, along with spaces. Other characters are
+/// part of words, like the `#` of `C#`. Every one costs a nested `replace`
+/// in `normalized_title`, and SQLite's parser only takes so many.
+const SEPARATORS: &str = ".,_-()[]:;'\"!?&+/*";
+
+/// Whether `c` separates words
+pub fn is_separator(c: char) -> bool {
+    c == ' ' || SEPARATORS.contains(c)
+}
+
+/// Options of FTS5's `ascii` tokenizer splitting on `SEPARATORS` only. It
+/// splits on all ASCII punctuation unless told otherwise.
+pub fn fts_tokenizer() -> String {
+    let tokenchars = (0..128u8)
+        .map(char::from)
+        .filter(|&c| c.is_ascii_punctuation() && !is_separator(c))
+        .collect::<String>();
+    format!("ascii tokenchars '{tokenchars}'")
+}
+
+/// The title with every separator turned into a space, runs of them into a
+/// single one, and a space on each end. So `GLOB '* word *'` finds whole
+/// words, and phrases match across `Show - Title`.
+fn normalized_title() -> String {
+    let mut title = "title".to_string();
+    for c in SEPARATORS.chars() {
+        title = format!("replace({title}, char({}), ' ')", u32::from(c));
+    }
+    // Runs of up to 8
+    for _ in 0..3 {
+        title = format!("replace({title}, '  ', ' ')");
+    }
+    format!("(' ' || {title} || ' ')")
+}
+
+/// A word, or several that must appear in that order
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term {
+    words: Vec<String>,
+    /// Whether the last word may be followed by more letters
+    prefix: bool,
+}
+
+impl Term {
+    fn parse(text: &str) -> Option<Self> {
+        let words = text
+            .split(is_separator)
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+        if words.is_empty() {
+            return None;
+        }
+        Some(Self {
+            words,
+            prefix: text.trim_end().ends_with('*'),
+        })
+    }
+
+    fn fts(&self) -> String {
+        let star = if self.prefix { "*" } else { "" };
+        format!("\"{}\"{star}", self.words.join(" "))
+    }
+
+    /// `GLOB` pattern matching the normalized title. SQLite only folds the
+    /// case of ASCII letters, so every letter matches both its cases.
+    fn glob(&self) -> String {
+        let words = self
+            .words
+            .iter()
+            .map(|word| word.chars().map(glob_char).collect::<String>())
+            .collect::<Vec<_>>();
+        let end = if self.prefix { "*" } else { " *" };
+        format!("* {}{end}", words.join(" "))
+    }
+}
+
+/// Alternatives, any of which satisfies the clause
+type Clause = Vec<Term>;
+
+/// A parsed `search_string`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    include: Vec<Clause>,
+    exclude: Vec<Clause>,
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Self {
+        let mut query = Self::default();
+        let mut chars = input.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let exclude = chars.next_if_eq(&'-').is_some();
+            let mut clause = Clause::new();
+            loop {
+                let mut text = String::new();
+                if chars.next_if_eq(&'"').is_some() {
+                    // An unterminated phrase runs to the end of the input
+                    text.extend(chars.by_ref().take_while(|&c| c != '"'));
+                } else {
+                    while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != '|') {
+                        text.push(c);
+                    }
+                }
+                clause.extend(Term::parse(&text));
+                if chars.next_if_eq(&'|').is_none() {
+                    break;
+                }
+            }
+
+            if clause.is_empty() {
+                continue;
+            }
+            if exclude {
+                query.exclude.push(clause);
+            } else {
+                query.include.push(clause);
+            }
+        }
+
+        query
+    }
+
+    /// Whether the full-text index can find the matching items, rather than
+    /// only rule some out
+    pub fn ranks(&self) -> bool {
+        !self.include.is_empty()
+    }
+
+    /// FTS5 query for the items to include, `None` without included terms
+    pub fn fts_match(&self) -> Option<String> {
+        if !self.ranks() {
+            return None;
+        }
+        let include = self
+            .include
+            .iter()
+            .map(fts_clause)
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        match self.fts_exclude() {
+            Some(exclude) => Some(format!("({include}) NOT ({exclude})")),
+            None => Some(include),
+        }
+    }
+
+    /// FTS5 query for the items to exclude, `None` without excluded terms
+    fn fts_exclude(&self) -> Option<String> {
+        if self.exclude.is_empty() {
+            return None;
+        }
+        Some(
+            self.exclude
+                .iter()
+                .map(fts_clause)
+                .collect::<Vec<_>>()
+                .join(" OR "),
+        )
+    }
+
+    /// Appends the conditions on the title to a `WHERE` clause. With
+    /// `fts_schema`, the source of the query must already have been joined
+    /// with the `fts_match` results, so only the exclusions are left.
+    pub fn push_conditions(&self, query: &mut QueryBuilder<'_, Sqlite>, fts_schema: Option<&str>) {
+        match fts_schema {
+            Some(_) if self.ranks() => {}
+            Some(schema) => {
+                if let Some(exclude) = self.fts_exclude() {
+                    query.push(format!(
+                        " AND id NOT IN (SELECT rowid FROM {schema}.items_fts WHERE items_fts MATCH "
+                    ));
+                    query.push_bind(exclude).push(")");
+                }
+            }
+            None => {
+                for clause in &self.include {
+                    query.push(" AND ");
+                    push_glob(query, clause);
+                }
+                for clause in &self.exclude {
+                    query.push(" AND NOT ");
+                    push_glob(query, clause);
+                }
+            }
+        }
+    }
+}
+
+fn fts_clause(clause: &Clause) -> String {
+    let terms = clause.iter().map(Term::fts).collect::<Vec<_>>();
+    format!("({})", terms.join(" OR "))
+}
+
+/// `c` in a `GLOB` pattern, as a class of its cases. `*`, `?`, `[` and `]`
+/// are separators, so nothing in a word means anything to `GLOB`.
+fn glob_char(c: char) -> String {
+    let mut cases = vec![c];
+    for other in [c.to_lowercase().to_string(), c.to_uppercase().to_string()] {
+        // Letters whose other case takes several characters match as given
+        let mut other = other.chars();
+        if let (Some(other), None) = (other.next(), other.next()) {
+            if !cases.contains(&other) {
+                cases.push(other);
+            }
+        }
+    }
+    match cases.len() {
+        1 => c.to_string(),
+        _ => format!("[{}]", cases.into_iter().collect::<String>()),
+    }
+}
+
+fn push_glob(query: &mut QueryBuilder<'_, Sqlite>, clause: &Clause) {
+    let title = normalized_title();
+    query.push("(");
+    for (i, term) in clause.iter().enumerate() {
+        if i > 0 {
+            query.push(" OR ");
+        }
+        query.push(&title).push(" GLOB ");
+        query.push_bind(term.glob());
+    }
+    query.push(")");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SearchQuery, Term};
+
+    fn term(words: &[&str], prefix: bool) -> Term {
+        Term {
+            words: words.iter().map(|w| w.to_string()).collect(),
+            prefix,
+        }
+    }
+
+    /// Input, then included and excluded clauses
+    type Case<'a> = (&'a str, &'a [&'a [Term]], &'a [&'a [Term]]);
+
+    #[test]
+    fn parses_queries() {
+        let cases: &[Case] = &[
+            ("", &[], &[]),
+            ("  -  |  \"\" ", &[], &[]),
+            (
+                "The Office US",
+                &[
+                    &[term(&["the"], false)],
+                    &[term(&["office"], false)],
+                    &[term(&["us"], false)],
+                ],
+                &[],
+            ),
+            (
+                "The.Office.US",
+                &[&[term(&["the", "office", "us"], false)]],
+                &[],
+            ),
+            (
+                "\"the office\" -us",
+                &[&[term(&["the", "office"], false)]],
+                &[&[term(&["us"], false)]],
+            ),
+            (
+                "office 720p|1080p|\"web dl\"",
+                &[
+                    &[term(&["office"], false)],
+                    &[
+                        term(&["720p"], false),
+                        term(&["1080p"], false),
+                        term(&["web", "dl"], false),
+                    ],
+                ],
+                &[],
+            ),
+            (
+                "off* -x26*|xvid",
+                &[&[term(&["off"], true)]],
+                &[&[term(&["x26"], true), term(&["xvid"], false)]],
+            ),
+            (
+                "\"unterminated phrase",
+                &[&[term(&["unterminated", "phrase"], false)]],
+                &[],
+            ),
+            ("x-men", &[&[term(&["x", "men"], false)]], &[]),
+            ("Marvel's", &[&[term(&["marvel", "s"], false)]], &[]),
+            ("ÉLITE", &[&[term(&["élite"], false)]], &[]),
+        ];
+
+        for (input, include, exclude) in cases {
+            let query = SearchQuery::parse(input);
+            let include = include.iter().map(|c| c.to_vec()).collect::<Vec<_>>();
+            let exclude = exclude.iter().map(|c| c.to_vec()).collect::<Vec<_>>();
+            assert_eq!(query.include, include, "{input}");
+            assert_eq!(query.exclude, exclude, "{input}");
+        }
+    }
+
+    #[test]
+    fn compiles_to_fts() {
+        let query = SearchQuery::parse("The.Office 720p|1080p -\"us version\" -x26*");
+        assert_eq!(
+            query.fts_match().as_deref(),
+            Some(r#"(("the office") AND ("720p" OR "1080p")) NOT (("us version") OR ("x26"*))"#)
+        );
+
+        let query = SearchQuery::parse("-us");
+        assert_eq!(query.fts_match(), None);
+        assert_eq!(query.fts_exclude().as_deref(), Some(r#"("us")"#));
+    }
+
+    #[test]
+    fn compiles_to_glob() {
+        assert_eq!(
+            term(&["the", "office"], false).glob(),
+            "* [tT][hH][eE] [oO][fF][fF][iI][cC][eE] *"
+        );
+        assert_eq!(term(&["off"], true).glob(), "* [oO][fF][fF]*");
+        assert_eq!(
+            term(&["élite", "2"], false).glob(),
+            "* [éÉ][lL][iI][tT][eE] 2 *"
+        );
+    }
+}
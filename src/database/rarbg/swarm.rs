@@ -0,0 +1,137 @@
+//! Seeders/leechers collected from trackers. They live in their own table so
+//! the `items` dump is never modified.
+
+use serde::Deserialize;
+use sqlx::QueryBuilder;
+
+use super::Database;
+
+/// Which items the swarm refresher looks at first
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshOrder {
+    /// Newest uploads first
+    #[default]
+    Newest,
+    /// Items returned by the most searches first. Items nobody searched for
+    /// are never refreshed in this mode.
+    MostQueried,
+}
+
+/// An item due for a scrape
+#[derive(Debug, sqlx::FromRow)]
+pub struct StaleItem {
+    pub id: i64,
+    pub hash: String,
+}
+
+/// Highest counts reported by any of the scraped trackers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwarmCounts {
+    pub seeders: i64,
+    pub leechers: i64,
+}
+
+impl Database {
+    pub(super) async fn create_swarm_stats(&self) -> anyhow::Result<()> {
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {}.swarm_stats (
+                item_id INTEGER PRIMARY KEY,
+                seeders INTEGER,
+                leechers INTEGER,
+                -- unix timestamp of the last scrape, NULL if never scraped
+                scraped_at INTEGER,
+                -- number of searches that returned the item
+                hits INTEGER NOT NULL DEFAULT 0
+            )"#,
+            self.side_schema
+        ))
+        .execute(&self.db)
+        .await?;
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {}.swarm_stats_hits ON swarm_stats (hits)",
+            self.side_schema
+        ))
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Up to `limit` items never scraped, or last scraped before `scraped_before`
+    pub async fn stale_items(
+        &self,
+        order: RefreshOrder,
+        scraped_before: i64,
+        limit: u32,
+    ) -> anyhow::Result<Vec<StaleItem>> {
+        let query = match order {
+            RefreshOrder::Newest => {
+                r#"
+                SELECT items.id, items.hash FROM items
+                LEFT JOIN swarm_stats ON swarm_stats.item_id = items.id
+                WHERE scraped_at IS NULL OR scraped_at < ?1
+                ORDER BY items.id DESC LIMIT ?2"#
+            }
+            RefreshOrder::MostQueried => {
+                r#"
+                SELECT items.id, items.hash FROM swarm_stats
+                JOIN items ON items.id = swarm_stats.item_id
+                WHERE hits > 0 AND (scraped_at IS NULL OR scraped_at < ?1)
+                ORDER BY hits DESC, items.id DESC LIMIT ?2"#
+            }
+        };
+
+        Ok(sqlx::query_as(query)
+            .bind(scraped_before)
+            .bind(limit)
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    /// Stores the result of a scrape. Items without `counts` (e.g. because
+    /// their hash is unusable) are only marked as scraped, so they don't come
+    /// back on every round.
+    pub async fn store_swarm_stats(
+        &self,
+        stats: &[(i64, Option<SwarmCounts>)],
+        scraped_at: i64,
+    ) -> anyhow::Result<()> {
+        if stats.is_empty() {
+            return Ok(());
+        }
+
+        let mut query =
+            QueryBuilder::new("INSERT INTO swarm_stats (item_id, seeders, leechers, scraped_at) ");
+        query.push_values(stats, |mut row, (id, counts)| {
+            row.push_bind(id)
+                .push_bind(counts.map(|c| c.seeders))
+                .push_bind(counts.map(|c| c.leechers))
+                .push_bind(scraped_at);
+        });
+        query.push(
+            r#"
+            ON CONFLICT (item_id) DO UPDATE SET
+                seeders = excluded.seeders,
+                leechers = excluded.leechers,
+                scraped_at = excluded.scraped_at"#,
+        );
+        query.build().execute(&self.db).await?;
+        Ok(())
+    }
+
+    /// Counts one more search hit for every item in `ids`
+    pub async fn add_hits(&self, ids: &[i64]) -> anyhow::Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = QueryBuilder::new("INSERT INTO swarm_stats (item_id, hits) ");
+        query.push_values(ids, |mut row, id| {
+            row.push_bind(id).push("1");
+        });
+        query.push(" ON CONFLICT (item_id) DO UPDATE SET hits = hits + 1");
+        query.build().execute(&self.db).await?;
+        Ok(())
+    }
+}
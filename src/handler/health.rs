@@ -1,10 +1,22 @@
+use axum::extract::State;
 use axum::routing::get;
-use axum::Router;
+use axum::{Json, Router};
+use serde_json::{json, Value};
 
-pub fn router() -> Router {
-    Router::new().route("/live", get(health))
+use super::AppState;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/live", get(health))
+        .route("/stats", get(stats))
+        .with_state(state)
 }
 
 async fn health() -> &'static str {
     "Ok"
 }
+
+/// Counters for keeping an eye on the lookups Sonarr/Radarr are spared
+async fn stats(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({ "id_cache": state.id_cache.stats() }))
+}
@@ -0,0 +1,123 @@
+//! Token bucket rate limiting, the way torrentapi paced its clients. Every
+//! IP gets a bucket, and so does every token, for clients using one from
+//! several IPs.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+
+/// Buckets kept before the full ones, which are no different from new
+/// ones, are dropped
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LimitKey {
+    Token(String),
+    Ip(IpAddr),
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Requests added to a bucket per second
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<LimitKey, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    requests: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            rate: config.rate,
+            burst: config.burst.into(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a request out of `key`'s bucket, or tells how long until it
+    /// holds one again
+    pub fn check(&self, key: LimitKey) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: LimitKey, now: Instant) -> Result<(), Duration> {
+        // The map is never left half-updated, a panic elsewhere can't hurt it
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| self.refill(*bucket, now).requests < self.burst);
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            requests: self.burst,
+            updated: now,
+        });
+        *bucket = self.refill(*bucket, now);
+        if bucket.requests < 1.0 {
+            let missing = 1.0 - bucket.requests;
+            return Err(Duration::from_secs_f64(missing / self.rate));
+        }
+        bucket.requests -= 1.0;
+        Ok(())
+    }
+
+    fn refill(&self, bucket: Bucket, now: Instant) -> Bucket {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        Bucket {
+            requests: (bucket.requests + elapsed * self.rate).min(self.burst),
+            updated: now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+
+    use super::{LimitKey, RateLimiter};
+    use crate::config::RateLimitConfig;
+
+    #[test]
+    fn limits_each_key() {
+        let limiter = RateLimiter::new(&RateLimitConfig {
+            enabled: true,
+            rate: 0.5,
+            burst: 2,
+        });
+        let start = Instant::now();
+        let token = || LimitKey::Token("token".to_string());
+        let ip = LimitKey::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        assert!(limiter.check_at(token(), start).is_ok());
+        assert!(limiter.check_at(token(), start).is_ok());
+        assert_eq!(
+            limiter.check_at(token(), start),
+            Err(Duration::from_secs(2))
+        );
+        // Other keys have buckets of their own
+        assert!(limiter.check_at(ip, start).is_ok());
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(
+            limiter.check_at(token(), later),
+            Err(Duration::from_secs(1))
+        );
+        let refilled = start + Duration::from_secs(2);
+        assert!(limiter.check_at(token(), refilled).is_ok());
+        assert!(limiter.check_at(token(), refilled).is_err());
+
+        // Never more than the burst, however long the quiet spell
+        let idle = start + Duration::from_secs(3600);
+        assert!(limiter.check_at(token(), idle).is_ok());
+        assert!(limiter.check_at(token(), idle).is_ok());
+        assert!(limiter.check_at(token(), idle).is_err());
+    }
+}
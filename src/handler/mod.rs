@@ -1,58 +1,433 @@
-use axum::http::StatusCode;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::{header, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::database::filter::allowed_categories;
+use crate::database::ids::{IdMapping, IdSource};
+use crate::database::rarbg::category::Category;
+use crate::database::rarbg::swarm::RefreshOrder;
+use crate::database::{Database, Item, SearchError};
+use crate::tvdb::cache::LookupCache;
+use crate::tvdb::{normalize_imdb_id, RadarrClient, SonarrClient};
+
+use self::limiter::{LimitKey, RateLimiter};
+use self::token::Tokens;
 
 pub mod health;
+pub mod limiter;
+pub mod token;
 pub mod torrentsapi;
+pub mod torznab;
 
-struct AppError(anyhow::Error);
-struct AppResult<T, E>(Result<T, E>);
+/// Shared by every API flavour
+#[derive(Debug, Clone)]
+pub struct AppState {
+    db: Arc<dyn Database>,
+    sonarr_client: SonarrClient,
+    /// Only there when Radarr is configured
+    radarr_client: Option<RadarrClient>,
+    id_cache: Arc<LookupCache<(IdSource, String), String>>,
+    /// Whether ids Sonarr/Radarr found are stored in `id_mappings`
+    persist_ids: bool,
+    /// Only there when `pubapi_v2.php` wants tokens
+    tokens: Option<Arc<Tokens>>,
+    limiter: Option<Arc<RateLimiter>>,
+    trackers: Arc<[String]>,
+    /// Categories the content filter lets through
+    categories: Arc<[Category]>,
+    /// Whether results sent to clients count as search hits, which only the
+    /// swarm refresher's `most_queried` order looks at
+    count_hits: bool,
+}
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .header("Content-Type", "application/json")
-            .body(axum::body::boxed(
-                json!({ "error": self.0.to_string() }).to_string(),
-            ))
-            .unwrap_or_else(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("error: {}", self.0),
-                )
-                    .into_response()
-            })
-    }
-}
-
-impl<T, E> IntoResponse for AppResult<T, E>
-where
-    T: IntoResponse,
-    E: Into<AppError>,
-{
-    fn into_response(self) -> Response {
-        match self.0 {
-            Ok(o) => o.into_response(),
-            Err(e) => e.into().into_response(),
+impl AppState {
+    pub fn new(config: &Config, db: Arc<dyn Database>) -> anyhow::Result<Self> {
+        Ok(Self {
+            db,
+            sonarr_client: SonarrClient::new(&config.sonarr)?,
+            radarr_client: RadarrClient::new(&config.radarr),
+            id_cache: Arc::new(LookupCache::new(&config.lookup_cache)),
+            persist_ids: config.lookup_cache.persist,
+            tokens: config
+                .tokens
+                .enabled
+                .then(|| Arc::new(Tokens::new(&config.tokens))),
+            limiter: config
+                .rate_limit
+                .enabled
+                .then(|| Arc::new(RateLimiter::new(&config.rate_limit))),
+            trackers: config.magnet.trackers.clone().into(),
+            categories: allowed_categories(&config.content_filter)?
+                .unwrap_or_else(|| Category::all().collect())
+                .into(),
+            count_hits: config.swarm.enabled && config.swarm.order == RefreshOrder::MostQueried,
+        })
+    }
+
+    /// Counts `items`, about to be sent to a client, as search hits. In the
+    /// background, as it isn't worth failing or holding up the search over.
+    fn record_hits(&self, items: &[Item]) {
+        if !self.count_hits || items.is_empty() {
+            return;
+        }
+        let db = self.db.clone();
+        let items = items.to_vec();
+        tokio::spawn(async move {
+            if let Err(e) = db.record_hits(&items).await {
+                warn!("Could not record search hits: {}", e);
+            }
+        });
+    }
+
+    /// Takes a request out of `key`'s bucket when rate limiting is on, or
+    /// tells how long until it holds one again
+    fn rate_limit(&self, key: LimitKey) -> Result<(), Duration> {
+        match &self.limiter {
+            Some(limiter) => limiter.check(key),
+            None => Ok(()),
+        }
+    }
+
+    /// IMDb id of a TVDB or TMDB id. The cache and local mappings are asked
+    /// first, Sonarr/Radarr only for ids neither knows.
+    async fn imdb_id_for(&self, source: IdSource, id: &str) -> anyhow::Result<Option<String>> {
+        let id = id.trim();
+        self.id_cache
+            .get_or_fetch((source, id.to_string()), || self.lookup_imdb_id(source, id))
+            .await
+    }
+
+    async fn lookup_imdb_id(&self, source: IdSource, id: &str) -> anyhow::Result<Option<String>> {
+        if let Some(imdb_id) = self.db.imdb_id(source, id).await? {
+            return Ok(Some(imdb_id));
+        }
+
+        let imdb_id = match source {
+            IdSource::Tvdb => {
+                let show = self.sonarr_client.get_series_metadata(id).await?;
+                show.into_iter().find_map(|s| s.imdb_id)
+            }
+            IdSource::Tmdb => {
+                let radarr = self
+                    .radarr_client
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("TMDB lookups need radarr.api_key"))?;
+                radarr.get_movie_metadata(id).await?.and_then(|m| m.imdb_id)
+            }
+        };
+        let Some(imdb_id) = imdb_id.as_deref().and_then(normalize_imdb_id) else {
+            return Ok(None);
+        };
+
+        if !self.persist_ids {
+            return Ok(Some(imdb_id));
+        }
+        let mapping = IdMapping {
+            source,
+            external_id: id.to_string(),
+            imdb_id: imdb_id.clone(),
+        };
+        if let Err(e) = self.db.store_id_mappings(&[mapping]).await {
+            warn!("Could not store the IMDb id of {:?} {}: {}", source, id, e);
+        }
+        Ok(Some(imdb_id))
+    }
+}
+
+/// Why a torrentapi request failed. Answered like torrentapi did, with an
+/// `error_code` the *arr apps branch on and the message in `error`:
+///
+/// | error_code | status | meaning                                        |
+/// |------------|--------|------------------------------------------------|
+/// | 1          | 401    | no `token`                                     |
+/// | 2          | 401    | a `token` that wasn't handed out for `app_id`  |
+/// | 4          | 401    | an expired `token`                             |
+/// | 5          | 429    | over the rate limit, see `Retry-After`         |
+/// | 8          | 400    | a parameter that doesn't parse                 |
+/// | 9          | 200    | `search_imdb` isn't an IMDb id                 |
+/// | 10         | 200    | the TVDB/TMDB id doesn't lead to an IMDb id    |
+/// | 20         | 200    | nothing was found                              |
+/// | 100        | 502    | Sonarr/Radarr couldn't be asked (arrbg's own)  |
+/// | 101        | 500    | the database failed (arrbg's own)              |
+/// | 102        | 502    | no source could be searched (arrbg's own)      |
+///
+/// torrentapi sent its codes with a 200, and clients only look at the body
+/// of those, so ids that lead nowhere stay 200 like an empty search.
+#[derive(Debug)]
+pub enum ApiError {
+    MissingToken,
+    InvalidToken,
+    ExpiredToken,
+    /// Along with how long until the next request is allowed
+    RateLimited(Duration),
+    InvalidParameter(String),
+    InvalidImdb(String),
+    ImdbNotFound,
+    NoResults,
+    Lookup(anyhow::Error),
+    Database(anyhow::Error),
+    /// Every source of a federated search failed
+    Unavailable(SearchError),
+}
+
+impl ApiError {
+    /// A failed search, blamed on the request where it was the request's
+    /// fault
+    pub fn search(e: anyhow::Error) -> Self {
+        match e.downcast::<SearchError>() {
+            Ok(SearchError::InvalidRequest(e)) => ApiError::InvalidParameter(e),
+            Ok(e @ SearchError::Unavailable(_)) => ApiError::Unavailable(e),
+            Err(e) => ApiError::Database(e),
+        }
+    }
+
+    pub fn code(&self) -> u32 {
+        match self {
+            ApiError::MissingToken => 1,
+            ApiError::InvalidToken => 2,
+            ApiError::ExpiredToken => 4,
+            ApiError::RateLimited(_) => 5,
+            ApiError::InvalidParameter(_) => 8,
+            ApiError::InvalidImdb(_) => 9,
+            ApiError::ImdbNotFound => 10,
+            ApiError::NoResults => 20,
+            ApiError::Lookup(_) => 100,
+            ApiError::Database(_) => 101,
+            ApiError::Unavailable(_) => 102,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::MissingToken | ApiError::InvalidToken | ApiError::ExpiredToken => {
+                StatusCode::UNAUTHORIZED
+            }
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidImdb(_) | ApiError::ImdbNotFound | ApiError::NoResults => {
+                StatusCode::OK
+            }
+            ApiError::Lookup(_) | ApiError::Unavailable(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::MissingToken => f.write_str("No token set!"),
+            ApiError::InvalidToken => f.write_str("Invalid token set!"),
+            ApiError::ExpiredToken => f.write_str("Invalid token. Use get_token for a new one!"),
+            ApiError::RateLimited(_) => {
+                f.write_str("Too many requests per second. Please try again later!")
+            }
+            ApiError::InvalidParameter(e) => write!(f, "Invalid parameter: {e}"),
+            ApiError::InvalidImdb(imdb) => write!(f, "Invalid imdb '{imdb}'"),
+            ApiError::ImdbNotFound => {
+                f.write_str("Cant find imdb in database. Are you sure this imdb exists?")
+            }
+            ApiError::NoResults => f.write_str("No results found"),
+            ApiError::Lookup(e) => write!(f, "Id lookup failed: {e:#}"),
+            ApiError::Database(e) => write!(f, "Database error: {e:#}"),
+            ApiError::Unavailable(e) => write!(f, "Search failed: {e}"),
         }
     }
 }
 
-impl<T, E> From<Result<T, E>> for AppResult<T, E>
-where
-    E: Into<AppError>,
-{
-    fn from(value: Result<T, E>) -> Self {
-        Self(value)
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self.status() {
+            status if status.is_server_error() => warn!("{}", self),
+            _ => debug!("{}", self),
+        }
+        let mut body = json!({
+            "error": self.to_string(),
+            "error_code": self.code(),
+        });
+        // Clients that don't know the codes still read an empty result
+        if let ApiError::NoResults = self {
+            body["torrent_results"] = json!([]);
+        }
+        let mut response = (
+            self.status(),
+            [(header::CONTENT_TYPE, "application/json")],
+            body.to_string(),
+        )
+            .into_response();
+        if let ApiError::RateLimited(wait) = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after(wait));
+        }
+        response
     }
 }
 
-impl<E> From<E> for AppError
-where
-    E: Into<anyhow::Error>,
-{
-    fn from(value: E) -> Self {
-        Self(value.into())
+/// `Retry-After` for a client that must wait `wait`. Whole seconds, rounded
+/// up so clients don't come back too early.
+fn retry_after(wait: Duration) -> HeaderValue {
+    HeaderValue::from(wait.as_secs() + u64::from(wait.subsec_nanos() > 0))
+}
+
+/// Rows without a usable infohash can't be turned into a magnet link, so
+/// there is nothing a client could do with them.
+fn skip_invalid<T>(torrent: anyhow::Result<T>) -> Option<T> {
+    torrent.map_err(|e| warn!("Skipping result: {}", e)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::body::HttpBody;
+    use axum::http::{header, StatusCode};
+    use axum::response::IntoResponse;
+    use secrecy::Secret;
+    use serde_json::{json, Value};
+
+    use super::{ApiError, AppState};
+    use crate::config::{LookupCacheConfig, RadarrConfig, SonarrConfig};
+    use crate::database::ids::{parse_id_mappings, IdSource};
+    use crate::database::rarbg::category::Category;
+    use crate::database::rarbg::test_db;
+    use crate::database::{SearchError, SourceFailure};
+    use crate::tvdb::cache::LookupCache;
+    use crate::tvdb::{RadarrClient, SonarrClient};
+
+    #[tokio::test]
+    async fn resolves_ids_without_sonarr() {
+        let db = test_db("").await;
+        let fixture = include_str!("../../fixtures/id_mappings.csv");
+        db.insert_id_mappings(&parse_id_mappings(fixture).unwrap())
+            .await
+            .unwrap();
+
+        // Nothing listens on the discard port
+        let offline = SonarrConfig {
+            url: "http://127.0.0.1:9".to_string(),
+            api_key: Some(Secret::new("key".to_string())),
+        };
+        let state = AppState {
+            db: Arc::new(db),
+            sonarr_client: SonarrClient::new(&offline).unwrap(),
+            radarr_client: RadarrClient::new(&RadarrConfig::default()),
+            id_cache: Arc::new(LookupCache::new(&LookupCacheConfig::default())),
+            persist_ids: true,
+            tokens: None,
+            limiter: None,
+            trackers: Vec::new().into(),
+            categories: Category::all().collect(),
+            count_hits: false,
+        };
+
+        assert_eq!(
+            state.imdb_id_for(IdSource::Tvdb, "81189").await.unwrap(),
+            Some("tt0903747".to_string())
+        );
+        assert_eq!(
+            state.imdb_id_for(IdSource::Tmdb, "603").await.unwrap(),
+            Some("tt0133093".to_string())
+        );
+        assert!(state.imdb_id_for(IdSource::Tvdb, "12345").await.is_err());
+        assert!(state.imdb_id_for(IdSource::Tmdb, "1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_with_torrentapi_codes() {
+        let cases = [
+            (
+                ApiError::NoResults,
+                StatusCode::OK,
+                json!({
+                    "error": "No results found",
+                    "error_code": 20,
+                    "torrent_results": [],
+                }),
+            ),
+            (
+                ApiError::ImdbNotFound,
+                StatusCode::OK,
+                json!({
+                    "error": "Cant find imdb in database. Are you sure this imdb exists?",
+                    "error_code": 10,
+                }),
+            ),
+            (
+                ApiError::InvalidParameter("unknown category 'tv_4k'".to_string()),
+                StatusCode::BAD_REQUEST,
+                json!({
+                    "error": "Invalid parameter: unknown category 'tv_4k'",
+                    "error_code": 8,
+                }),
+            ),
+            (
+                ApiError::ExpiredToken,
+                StatusCode::UNAUTHORIZED,
+                json!({
+                    "error": "Invalid token. Use get_token for a new one!",
+                    "error_code": 4,
+                }),
+            ),
+            (
+                ApiError::Lookup(anyhow::anyhow!("connection refused")),
+                StatusCode::BAD_GATEWAY,
+                json!({
+                    "error": "Id lookup failed: connection refused",
+                    "error_code": 100,
+                }),
+            ),
+            (
+                ApiError::search(
+                    SearchError::InvalidRequest("cursor wasn't handed out by this search".into())
+                        .into(),
+                ),
+                StatusCode::BAD_REQUEST,
+                json!({
+                    "error": "Invalid parameter: cursor wasn't handed out by this search",
+                    "error_code": 8,
+                }),
+            ),
+            (
+                ApiError::search(anyhow::anyhow!("disk I/O error")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({
+                    "error": "Database error: disk I/O error",
+                    "error_code": 101,
+                }),
+            ),
+            (
+                ApiError::search(
+                    SearchError::Unavailable(vec![SourceFailure {
+                        source: "rarbg".to_string(),
+                        error: "database is locked".to_string(),
+                    }])
+                    .into(),
+                ),
+                StatusCode::BAD_GATEWAY,
+                json!({
+                    "error": "Search failed: every source failed: rarbg (database is locked)",
+                    "error_code": 102,
+                }),
+            ),
+        ];
+
+        for (error, status, body) in cases {
+            let mut response = error.into_response();
+            assert_eq!(response.status(), status);
+            let bytes = response.body_mut().data().await.unwrap().unwrap();
+            assert_eq!(serde_json::from_slice::<Value>(&bytes).unwrap(), body);
+        }
+
+        let limited = ApiError::RateLimited(Duration::from_millis(1500)).into_response();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers()[header::RETRY_AFTER], "2");
     }
 }
@@ -0,0 +1,121 @@
+//! torrentapi's tokens. `get_token` hands out `<expiry>.<signature>`, signed
+//! over the client's `app_id` and the expiry, so checking one needs nothing
+//! but the secret.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+use crate::config::TokenConfig;
+
+use super::ApiError;
+
+/// Bytes of the signature kept in a token
+const SIGNATURE_LEN: usize = 16;
+
+#[derive(Debug)]
+pub struct Tokens {
+    secret: Secret<String>,
+    ttl: Duration,
+}
+
+impl Tokens {
+    pub fn new(config: &TokenConfig) -> Self {
+        let secret = config.secret.clone().unwrap_or_else(|| {
+            let mut secret = [0; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            Secret::new(hex::encode(secret))
+        });
+        Self {
+            secret,
+            ttl: Duration::from_secs(config.ttl),
+        }
+    }
+
+    pub fn issue(&self, app_id: &str) -> String {
+        self.issue_at(app_id, unix_now())
+    }
+
+    pub fn verify(&self, token: Option<&str>, app_id: &str) -> Result<(), ApiError> {
+        self.verify_at(token, app_id, unix_now())
+    }
+
+    fn issue_at(&self, app_id: &str, now: u64) -> String {
+        let expires = now + self.ttl.as_secs();
+        let signature = self.mac(app_id, expires).finalize().into_bytes();
+        format!("{expires}.{}", hex::encode(&signature[..SIGNATURE_LEN]))
+    }
+
+    fn verify_at(&self, token: Option<&str>, app_id: &str, now: u64) -> Result<(), ApiError> {
+        let token = token.ok_or(ApiError::MissingToken)?;
+        let (expires, signature) = token
+            .split_once('.')
+            .and_then(|(expires, signature)| {
+                Some((expires.parse::<u64>().ok()?, hex::decode(signature).ok()?))
+            })
+            .filter(|(_, signature)| signature.len() == SIGNATURE_LEN)
+            .ok_or(ApiError::InvalidToken)?;
+        self.mac(app_id, expires)
+            .verify_truncated_left(&signature)
+            .map_err(|_| ApiError::InvalidToken)?;
+        if expires <= now {
+            return Err(ApiError::ExpiredToken);
+        }
+        Ok(())
+    }
+
+    fn mac(&self, app_id: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.expose_secret().as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(app_id.as_bytes());
+        // The expiry has a fixed length, so app ids can't run into it
+        mac.update(&expires.to_be_bytes());
+        mac
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::Tokens;
+    use crate::config::TokenConfig;
+    use crate::handler::ApiError;
+
+    #[test]
+    fn verifies_tokens() {
+        let tokens = Tokens::new(&TokenConfig {
+            secret: Some(Secret::new("secret".to_string())),
+            ..Default::default()
+        });
+        let token = tokens.issue_at("sonarr", 1_000);
+        assert!(tokens.verify_at(Some(&token), "sonarr", 1_000).is_ok());
+        assert!(tokens.verify_at(Some(&token), "sonarr", 1_899).is_ok());
+
+        let code = |result: Result<(), ApiError>| result.unwrap_err().code();
+        assert_eq!(code(tokens.verify_at(Some(&token), "sonarr", 1_900)), 4);
+        assert_eq!(code(tokens.verify_at(Some(&token), "radarr", 1_000)), 2);
+        assert_eq!(code(tokens.verify_at(None, "sonarr", 1_000)), 1);
+        for forged in [
+            "bogus",
+            "99999.",
+            "1900.00",
+            &token.replace("1900.", "9999."),
+        ] {
+            assert_eq!(code(tokens.verify_at(Some(forged), "sonarr", 1_000)), 2);
+        }
+
+        // Tokens from another secret, or an earlier start without one
+        let other = Tokens::new(&TokenConfig::default());
+        assert_eq!(code(other.verify_at(Some(&token), "sonarr", 1_000)), 2);
+    }
+}
@@ -1,101 +1,344 @@
 use axum::body::Body;
-use axum::extract::{Query, State};
+use std::net::SocketAddr;
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{ConnectInfo, Query, State};
 use axum::http::Request;
 use axum::routing::get;
 use axum::Router;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use serde_with::formats::SemicolonSeparator;
-use serde_with::{serde_as, StringWithSeparator};
-use sqlx::SqlitePool;
+use serde_with::{serde_as, DisplayFromStr};
 use tracing::{debug, trace};
 
-use crate::database::rarbg::category::Category;
-use crate::database::rarbg::{Database, Pagination, RarbgTorrent};
-use crate::tvdb::SonarrClient;
+use crate::database::cursor::Cursor;
+use crate::database::ids::IdSource;
+use crate::database::rarbg::category::CategoryList;
+use crate::database::rarbg::{RarbgTorrent, RarbgTorrentSimple};
+use crate::database::{Pagination, SearchRequest, Sort, SourceFailure};
+use crate::release::{ReleaseFilter, Resolution, Source, VideoCodec};
+use crate::tvdb::normalize_imdb_id;
 
-use super::AppError;
+use super::limiter::LimitKey;
+use super::{skip_invalid, ApiError, AppState};
 
-#[derive(Debug, Clone)]
-struct AppState {
-    db: Database,
-    sonarr_client: SonarrClient,
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/pubapi_v2.php", get(api))
+        .with_state(state)
 }
 
-pub async fn router() -> anyhow::Result<Router> {
-    let db = SqlitePool::connect("sqlite:rarbg_db.sqlite").await?;
-    let sonarr_client = SonarrClient::new(
-        "http://localhost:8989".to_string(),
-        "77d2529ab0a8497eb144f2145d3d0bf5".to_string(),
-    )?;
-    Ok(Router::new()
-        .route("/pubapi_v2.php", get(api))
-        .with_state(AppState {
-            db: Database::new(db),
-            sonarr_client,
-        }))
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    #[default]
+    Search,
+    /// Latest torrents, optionally restricted to `category`
+    List,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Format {
+    /// Only `filename`, `category` and `download`
+    #[default]
+    Json,
+    JsonExtended,
 }
 
 #[serde_as]
 #[derive(Debug, Deserialize, Serialize)]
 struct Params {
+    #[serde(default)]
+    mode: Mode,
+    #[serde(default)]
+    format: Format,
+    #[serde(default)]
+    sort: Sort,
     search_string: Option<String>,
-    #[serde_as(as = "Option<StringWithSeparator::<SemicolonSeparator, Category>>")]
-    category: Option<Vec<Category>>,
+    /// From `get_token`
+    token: Option<String>,
+    /// Names the client, tokens are only good for the one they were
+    /// handed out to
+    app_id: Option<String>,
+    /// Ids, names or groups, see `CategoryList`
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    category: Option<CategoryList>,
     get_token: Option<String>,
     search_tvdb: Option<String>,
+    search_imdb: Option<String>,
+    search_themoviedb: Option<u64>,
+    search_season: Option<u16>,
+    search_episode: Option<u16>,
     limit: Option<usize>,
+    /// Results to skip, for clients paging the way they always have. Costs
+    /// a scan over every skipped result, unlike `cursor`.
+    offset: Option<usize>,
+    /// `next` of the previous page
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    cursor: Option<Cursor>,
+    min_seeders: Option<u32>,
+    /// Filters on what the release names tell
+    resolution: Option<Resolution>,
+    source: Option<Source>,
+    codec: Option<VideoCodec>,
+    group: Option<String>,
+    hdr: Option<bool>,
 }
 
 async fn api(
-    Query(params): Query<Params>,
+    params: Result<Query<Params>, QueryRejection>,
     State(state): State<AppState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
     req: Request<Body>,
-) -> Result<String, AppError> {
+) -> Result<String, ApiError> {
+    let Query(params) = params.map_err(|e| ApiError::InvalidParameter(e.body_text()))?;
     debug!("params: {:?}", params);
     debug!("uri: {:?}", req.uri());
 
+    // Every request counts against its IP, so fetching fresh tokens doesn't
+    // buy fresh buckets
+    state
+        .rate_limit(LimitKey::Ip(client.ip()))
+        .map_err(ApiError::RateLimited)?;
+    let app_id = params.app_id.as_deref().unwrap_or_default();
     if params.get_token.is_some() {
-        return Ok(json!({
-            "token": "bogus"
-        })
-        .to_string());
+        let token = match &state.tokens {
+            Some(tokens) => tokens.issue(app_id),
+            None => "bogus".to_string(),
+        };
+        return Ok(json!({ "token": token }).to_string());
     }
 
+    // A token used from several IPs is still one client
+    if let Some(tokens) = &state.tokens {
+        tokens.verify(params.token.as_deref(), app_id)?;
+        let token = params.token.clone().unwrap_or_default();
+        state
+            .rate_limit(LimitKey::Token(token))
+            .map_err(ApiError::RateLimited)?;
+    }
+
+    if let Some(cursor @ Cursor::After { key, .. }) = params.cursor {
+        if key.sort() != params.sort {
+            return Err(ApiError::InvalidParameter(format!(
+                "cursor {cursor} is for another sort"
+            )));
+        }
+    }
+    if params.offset.is_some() && params.cursor.is_some() {
+        return Err(ApiError::InvalidParameter(
+            "offset and cursor can't be combined".to_string(),
+        ));
+    }
     let pagination = Pagination {
+        offset: params.offset.unwrap_or_default(),
         amount: params.limit,
-        ..Default::default()
+        after: params.cursor,
     };
 
-    let imdb_id = if let Some(tvdb_id) = params.search_tvdb {
-        let show = state.sonarr_client.get_series_metadata(&tvdb_id).await?;
-        show.into_iter().find_map(|s| s.imdb_id)
-    } else {
-        None
+    let (search_string, imdb_id) = match params.mode {
+        Mode::List => (String::default(), None),
+        Mode::Search => {
+            let imdb_id = match lookup_imdb_id(&state, &params).await? {
+                Some(None) => return Err(ApiError::ImdbNotFound),
+                Some(imdb_id) => imdb_id,
+                None => None,
+            };
+            (params.search_string.clone().unwrap_or_default(), imdb_id)
+        }
     };
 
-    let r: Vec<RarbgTorrent> = state
+    let request = SearchRequest {
+        text: search_string,
+        categories: params
+            .category
+            .clone()
+            .map(|list| list.0)
+            .filter(|categories| !categories.is_empty()),
+        imdb_id,
+        pagination,
+        sort: params.sort,
+        min_seeders: params.min_seeders,
+        release: ReleaseFilter {
+            resolution: params.resolution,
+            source: params.source,
+            video_codec: params.codec,
+            group: params.group.clone(),
+            hdr: params.hdr,
+            season: params.search_season,
+            episode: params.search_episode,
+        },
+    };
+    let results = state
         .db
-        .search_query(
-            &params.search_string.unwrap_or_default(),
-            params.category.as_ref().map(|r| r.as_ref()),
-            imdb_id,
-            Some(pagination),
-        )
-        .await?
-        .into_iter()
-        .map(Into::into)
-        .collect();
+        .search_sources(&request)
+        .await
+        .map_err(ApiError::search)?;
+    let next = results
+        .resume
+        .or_else(|| state.db.next_cursor(&request, &results.items));
+    state.record_hits(&results.items);
+    let items = results.items.into_iter();
+
+    let torrent_results = match params.format {
+        Format::Json => json!(items
+            .filter_map(|item| skip_invalid(RarbgTorrentSimple::from_item(item, &state.trackers)))
+            .collect::<Vec<_>>()),
+        Format::JsonExtended => json!(items
+            .filter_map(|item| skip_invalid(RarbgTorrent::from_item(item, &state.trackers)))
+            .collect::<Vec<_>>()),
+    };
+    // A search that gave up may still find more further on, or tells why
+    // it can't
+    if torrent_results.as_array().is_some_and(|r| r.is_empty())
+        && next.is_none()
+        && results.failures.is_empty()
+    {
+        return Err(ApiError::NoResults);
+    }
+
+    Ok(response(torrent_results, next, &results.failures))
+}
+
+/// The IMDb id the `search_imdb`, `search_themoviedb` or `search_tvdb`
+/// parameter points at, in that order of preference. `None` when none of
+/// them is set, `Some(None)` when the id doesn't lead to an IMDb id: every
+/// result would be for some other title then.
+async fn lookup_imdb_id(
+    state: &AppState,
+    params: &Params,
+) -> Result<Option<Option<String>>, ApiError> {
+    if let Some(raw) = &params.search_imdb {
+        let imdb_id = normalize_imdb_id(raw).ok_or_else(|| ApiError::InvalidImdb(raw.clone()))?;
+        return Ok(Some(Some(imdb_id)));
+    }
+    let (source, id) = match (params.search_themoviedb, &params.search_tvdb) {
+        (Some(tmdb_id), _) => (IdSource::Tmdb, tmdb_id.to_string()),
+        (None, Some(tvdb_id)) => (IdSource::Tvdb, tvdb_id.clone()),
+        (None, None) => return Ok(None),
+    };
+    let imdb_id = state
+        .imdb_id_for(source, &id)
+        .await
+        .map_err(ApiError::Lookup)?;
+    Ok(Some(imdb_id))
+}
 
-    let res = json!({
-        "torrent_results": r,
+/// `next`, the cursor of the following page, and the sources that failed
+/// in `failed_sources` are additions torrentapi never sent
+fn response(
+    torrent_results: serde_json::Value,
+    next: Option<Cursor>,
+    failures: &[SourceFailure],
+) -> String {
+    let mut res = json!({
+        "torrent_results": torrent_results,
         "rate_limit": null,
         "error_code": null,
         "error": "",
-    })
-    .to_string();
+    });
+    if let Some(next) = next {
+        res["next"] = json!(next.to_string());
+    }
+    if !failures.is_empty() {
+        res["failed_sources"] = json!(failures);
+    }
+    let res = res.to_string();
 
     trace!("Responding with: {}", res);
 
-    Ok(res)
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::extract::{ConnectInfo, FromRequestParts, Query, State};
+    use axum::http::Request;
+    use secrecy::Secret;
+    use serde_json::Value;
+
+    use super::api;
+    use crate::config::Config;
+    use crate::database::rarbg::test_db;
+    use crate::handler::{ApiError, AppState};
+
+    async fn state(config: &mut Config, rows: &str) -> AppState {
+        config.sonarr.api_key = Some(Secret::new("key".to_string()));
+        AppState::new(config, Arc::new(test_db(rows).await)).unwrap()
+    }
+
+    /// Answers `pubapi_v2.php?{query}` as asked from `client`
+    async fn get(state: &AppState, client: [u8; 4], query: &str) -> Result<Value, ApiError> {
+        let req = Request::builder()
+            .uri(format!("/pubapi_v2.php?{query}"))
+            .body(Body::empty())
+            .unwrap();
+        let (mut parts, body) = req.into_parts();
+        let params = Query::from_request_parts(&mut parts, &()).await;
+        let req = Request::from_parts(parts, body);
+        let client = SocketAddr::from((client, 1337));
+        let body = api(params, State(state.clone()), ConnectInfo(client), req).await?;
+        Ok(serde_json::from_str(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn limits_clients_fetching_fresh_tokens() {
+        let mut config = Config::default();
+        config.tokens.enabled = true;
+        config.rate_limit.enabled = true;
+        config.rate_limit.burst = 3;
+        let state = state(&mut config, "").await;
+        let client = [192, 0, 2, 1];
+
+        for _ in 0..2 {
+            let token = get(&state, client, "get_token=get_token&app_id=test").await;
+            let token = token.unwrap()["token"].as_str().unwrap().to_string();
+            let listed = get(
+                &state,
+                client,
+                &format!("mode=list&token={token}&app_id=test"),
+            )
+            .await;
+            match listed {
+                // Still within the burst, the dump is empty
+                Err(ApiError::NoResults) => {}
+                Err(ApiError::RateLimited(_)) => return,
+                other => panic!("unexpected answer: {:?}", other.map(|_| ())),
+            }
+        }
+        panic!("a fresh token bought a fresh bucket");
+    }
+
+    #[tokio::test]
+    async fn pages_by_offset_like_by_cursor() {
+        let state = state(
+            &mut Config::default(),
+            r#"
+            INSERT INTO items (id, hash, title, dt, cat) VALUES
+                (1, '0101010101010101010101010101010101010101', 'One', '', 'movies'),
+                (2, '0202020202020202020202020202020202020202', 'Two', '', 'movies'),
+                (3, '0303030303030303030303030303030303030303', 'Three', '', 'movies'),
+                (4, '0404040404040404040404040404040404040404', 'Four', '', 'movies'),
+                (5, '0505050505050505050505050505050505050505', 'Five', '', 'movies');
+            "#,
+        )
+        .await;
+        let client = [192, 0, 2, 1];
+
+        let first = get(&state, client, "mode=list&limit=2").await.unwrap();
+        let next = first["next"].as_str().unwrap();
+        let followed = get(&state, client, &format!("mode=list&limit=2&cursor={next}")).await;
+        let skipped = get(&state, client, "mode=list&limit=2&offset=2").await;
+        let followed = followed.unwrap()["torrent_results"].clone();
+        assert_eq!(skipped.unwrap()["torrent_results"], followed);
+        assert_eq!(followed[0]["filename"], "Three");
+
+        let both = get(&state, client, &format!("mode=list&offset=2&cursor={next}")).await;
+        assert!(matches!(both, Err(ApiError::InvalidParameter(_))));
+    }
 }
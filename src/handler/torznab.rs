@@ -0,0 +1,601 @@
+//! Torznab API, as spoken by Prowlarr, Jackett and the *arr apps.
+//! Spec described at: https://torznab.github.io/spec-1.3-draft/
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::Writer;
+use serde::Deserialize;
+use serde_with::formats::CommaSeparator;
+use serde_with::{serde_as, StringWithSeparator};
+use tracing::{debug, trace, warn};
+
+use crate::database::ids::IdSource;
+use crate::database::rarbg::category::Category;
+use crate::database::rarbg::TorznabTorrent;
+use crate::database::{
+    Pagination, SearchError, SearchRequest, Sort, SourceFailure, DEFAULT_RESULTS, MAX_RESULTS,
+};
+use crate::release::ReleaseFilter;
+use crate::tvdb::normalize_imdb_id;
+
+use super::limiter::LimitKey;
+use super::{retry_after, skip_invalid, AppState};
+
+const RSS_CONTENT_TYPE: &str = "application/rss+xml; charset=utf-8";
+const XML_CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+
+pub fn router(state: AppState) -> Router {
+    Router::new().route("/api", get(api)).with_state(state)
+}
+
+/// The `t` parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Caps,
+    Search,
+    TvSearch,
+    Movie,
+    Music,
+}
+
+impl FromStr for Function {
+    type Err = TorznabError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "caps" => Ok(Function::Caps),
+            "search" => Ok(Function::Search),
+            "tvsearch" => Ok(Function::TvSearch),
+            "movie" => Ok(Function::Movie),
+            "music" => Ok(Function::Music),
+            _ => Err(TorznabError::new(202, format!("No such function ({s})"))),
+        }
+    }
+}
+
+impl Function {
+    /// Top level category searched when the request names none
+    fn default_category(&self) -> Option<u32> {
+        match self {
+            Function::TvSearch => Some(5000),
+            Function::Movie => Some(2000),
+            Function::Music => Some(3000),
+            Function::Caps | Function::Search => None,
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct Params {
+    t: Option<String>,
+    q: Option<String>,
+    #[serde_as(as = "Option<StringWithSeparator::<CommaSeparator, u32>>")]
+    cat: Option<Vec<u32>>,
+    imdbid: Option<String>,
+    tvdbid: Option<String>,
+    /// Daily shows send the year as season and `month/day` as episode, which
+    /// release names don't carry, so anything non numeric is ignored
+    season: Option<String>,
+    ep: Option<String>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+/// Newznab style error, answered as `<error code=".." description=".."/>`
+#[derive(Debug)]
+struct TorznabError {
+    code: u32,
+    description: String,
+    status: StatusCode,
+    /// How long a rate limited client must wait
+    retry_after: Option<Duration>,
+}
+
+impl TorznabError {
+    /// 900, the unknown error, is the server's fault, every other code the
+    /// request's
+    fn new(code: u32, description: String) -> Self {
+        let status = match code {
+            900 => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Self {
+            code,
+            description,
+            status,
+            retry_after: None,
+        }
+    }
+
+    /// Newznab's "request limit reached", with the same status and
+    /// `Retry-After` as `pubapi_v2.php` answers
+    fn rate_limited(wait: Duration) -> Self {
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(wait),
+            ..Self::new(500, "Request limit reached".to_string())
+        }
+    }
+
+    /// A failed search, blamed on the request where it was the request's
+    /// fault
+    fn search(e: anyhow::Error) -> Self {
+        match e.downcast::<SearchError>() {
+            Ok(SearchError::InvalidRequest(e)) => {
+                Self::new(201, format!("Incorrect parameter ({e})"))
+            }
+            Ok(e @ SearchError::Unavailable(_)) => {
+                warn!("Torznab search failed: {}", e);
+                Self {
+                    status: StatusCode::BAD_GATEWAY,
+                    ..Self::new(900, e.to_string())
+                }
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl<E> From<E> for TorznabError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(value: E) -> Self {
+        let error = value.into();
+        warn!("Torznab request failed: {:#}", error);
+        Self::new(900, error.to_string())
+    }
+}
+
+impl IntoResponse for TorznabError {
+    fn into_response(self) -> Response {
+        let status = self.status;
+        let retry_after = self
+            .retry_after
+            .map(|wait| [(header::RETRY_AFTER, retry_after(wait))]);
+        let mut writer = Writer::new(Vec::new());
+        let written = writer
+            .create_element("error")
+            .with_attribute(("code", self.code.to_string().as_str()))
+            .with_attribute(("description", self.description.as_str()))
+            .write_empty()
+            .map(|_| ());
+        match written {
+            Ok(()) => (
+                status,
+                retry_after,
+                [(header::CONTENT_TYPE, XML_CONTENT_TYPE)],
+                writer.into_inner(),
+            )
+                .into_response(),
+            Err(_) => (status, retry_after, self.description).into_response(),
+        }
+    }
+}
+
+async fn api(
+    params: Result<Query<Params>, QueryRejection>,
+    State(state): State<AppState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+) -> Result<Response, TorznabError> {
+    let Query(params) = params
+        .map_err(|e| TorznabError::new(201, format!("Incorrect parameter ({})", e.body_text())))?;
+    debug!("torznab params: {:?}", params);
+    // Torznab has no tokens, clients are told apart by IP
+    state
+        .rate_limit(LimitKey::Ip(client.ip()))
+        .map_err(TorznabError::rate_limited)?;
+
+    let function: Function = params
+        .t
+        .as_deref()
+        .ok_or_else(|| TorznabError::new(200, "Missing parameter (t)".to_string()))?
+        .parse()?;
+    if function == Function::Caps {
+        let caps = caps(&state.categories)?;
+        return Ok(([(header::CONTENT_TYPE, XML_CONTENT_TYPE)], caps).into_response());
+    }
+
+    let categories = categories(function, params.cat.as_deref());
+    let imdb_id = match function {
+        Function::Movie => match params.imdbid {
+            Some(raw) => Some(normalize_imdb_id(&raw).ok_or_else(|| {
+                TorznabError::new(201, format!("Incorrect parameter (imdbid {raw})"))
+            })?),
+            None => None,
+        },
+        Function::TvSearch => match params.tvdbid {
+            Some(tvdb_id) => {
+                match state.imdb_id_for(IdSource::Tvdb, &tvdb_id).await? {
+                    Some(imdb_id) => Some(imdb_id),
+                    // Every result would be for some other show
+                    None => return rss(&[], &[]),
+                }
+            }
+            None => None,
+        },
+        _ => None,
+    };
+    if categories.as_ref().is_some_and(|c| c.is_empty()) {
+        return rss(&[], &[]);
+    }
+
+    // Torznab only knows offsets
+    let pagination = Pagination {
+        offset: params.offset.unwrap_or_default(),
+        amount: params.limit,
+        after: None,
+    };
+    let request = SearchRequest {
+        text: params.q.unwrap_or_default(),
+        categories,
+        imdb_id,
+        pagination,
+        // Same as newest first for RSS syncs, which search nothing
+        sort: Sort::Relevance,
+        min_seeders: None,
+        release: ReleaseFilter {
+            season: params.season.and_then(|s| s.parse().ok()),
+            episode: params.ep.and_then(|e| e.parse().ok()),
+            ..Default::default()
+        },
+    };
+    let results = state
+        .db
+        .search_sources(&request)
+        .await
+        .map_err(TorznabError::search)?;
+    for failure in &results.failures {
+        warn!("Source {} failed: {}", failure.source, failure.error);
+    }
+    state.record_hits(&results.items);
+    let torrents = results
+        .items
+        .into_iter()
+        .filter_map(|item| skip_invalid(TorznabTorrent::from_item(item, &state.trackers)))
+        .collect::<Vec<_>>();
+
+    rss(&torrents, &results.failures)
+}
+
+fn rss(torrents: &[TorznabTorrent], failures: &[SourceFailure]) -> Result<Response, TorznabError> {
+    let body = feed(torrents, failures)?;
+    trace!("Responding with: {}", String::from_utf8_lossy(&body));
+    Ok(([(header::CONTENT_TYPE, RSS_CONTENT_TYPE)], body).into_response())
+}
+
+/// Categories matching the requested Torznab ids, either exactly or through
+/// their top level category. `None` searches everything.
+fn categories(function: Function, requested: Option<&[u32]>) -> Option<Vec<Category>> {
+    let requested = match requested {
+        Some(ids) if !ids.is_empty() => ids.to_vec(),
+        _ => vec![function.default_category()?],
+    };
+    Some(
+        Category::all()
+            .filter(|c| {
+                requested.contains(&c.torznab_id()) || requested.contains(&c.torznab_parent_id())
+            })
+            .collect(),
+    )
+}
+
+fn torznab_name(id: u32) -> &'static str {
+    match id {
+        1000 => "Console",
+        1180 => "Console/PS4",
+        2000 => "Movies",
+        2030 => "Movies/SD",
+        2040 => "Movies/HD",
+        2045 => "Movies/UHD",
+        2050 => "Movies/BluRay",
+        2060 => "Movies/3D",
+        3000 => "Audio",
+        3010 => "Audio/MP3",
+        3040 => "Audio/Lossless",
+        4000 => "PC",
+        4010 => "PC/0day",
+        4050 => "PC/Games",
+        5000 => "TV",
+        5030 => "TV/SD",
+        5040 => "TV/HD",
+        5045 => "TV/UHD",
+        6000 => "XXX",
+        _ => "Other",
+    }
+}
+
+/// Advertises `categories`, the ones the content filter lets through
+fn caps(categories: &[Category]) -> Result<Vec<u8>, quick_xml::Error> {
+    let mut tree = BTreeMap::<u32, BTreeSet<u32>>::new();
+    for category in categories {
+        let subcats = tree.entry(category.torznab_parent_id()).or_default();
+        if category.torznab_id() != category.torznab_parent_id() {
+            subcats.insert(category.torznab_id());
+        }
+    }
+
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer.create_element("caps").write_inner_content(|w| {
+        w.create_element("server")
+            .with_attribute(("title", "arrbg"))
+            .write_empty()?;
+        w.create_element("limits")
+            .with_attribute(("default", DEFAULT_RESULTS.to_string().as_str()))
+            .with_attribute(("max", MAX_RESULTS.to_string().as_str()))
+            .write_empty()?;
+        w.create_element("searching").write_inner_content(|w| {
+            for (name, params) in [
+                ("search", "q"),
+                ("tv-search", "q,tvdbid,season,ep"),
+                ("movie-search", "q,imdbid"),
+                ("music-search", "q"),
+            ] {
+                w.create_element(name)
+                    .with_attribute(("available", "yes"))
+                    .with_attribute(("supportedParams", params))
+                    .write_empty()?;
+            }
+            Ok::<_, quick_xml::Error>(())
+        })?;
+        w.create_element("categories").write_inner_content(|w| {
+            for (parent, subcats) in &tree {
+                w.create_element("category")
+                    .with_attribute(("id", parent.to_string().as_str()))
+                    .with_attribute(("name", torznab_name(*parent)))
+                    .write_inner_content(|w| {
+                        for id in subcats {
+                            w.create_element("subcat")
+                                .with_attribute(("id", id.to_string().as_str()))
+                                .with_attribute(("name", torznab_name(*id)))
+                                .write_empty()?;
+                        }
+                        Ok::<_, quick_xml::Error>(())
+                    })?;
+            }
+            Ok::<_, quick_xml::Error>(())
+        })?;
+        Ok::<_, quick_xml::Error>(())
+    })?;
+
+    Ok(writer.into_inner())
+}
+
+/// Sources that failed are named in the channel's description, Torznab has
+/// no place of its own for them
+fn feed(
+    torrents: &[TorznabTorrent],
+    failures: &[SourceFailure],
+) -> Result<Vec<u8>, quick_xml::Error> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("rss")
+        .with_attribute(("version", "2.0"))
+        .with_attribute(("xmlns:torznab", "http://torznab.com/schemas/2015/feed"))
+        .write_inner_content(|w| {
+            w.create_element("channel").write_inner_content(|w| {
+                w.create_element("title")
+                    .write_text_content(BytesText::new("arrbg"))?;
+                if !failures.is_empty() {
+                    let failed = failures
+                        .iter()
+                        .map(|f| format!("{} ({})", f.source, f.error))
+                        .collect::<Vec<_>>();
+                    let description = format!("Failed sources: {}", failed.join(", "));
+                    w.create_element("description")
+                        .write_text_content(BytesText::new(&description))?;
+                }
+                for torrent in torrents {
+                    item(w, torrent)?;
+                }
+                Ok::<_, quick_xml::Error>(())
+            })?;
+            Ok::<_, quick_xml::Error>(())
+        })?;
+
+    Ok(writer.into_inner())
+}
+
+fn item(writer: &mut Writer<Vec<u8>>, torrent: &TorznabTorrent) -> Result<(), quick_xml::Error> {
+    let size = torrent.size.unwrap_or_default().to_string();
+    writer.create_element("item").write_inner_content(|w| {
+        w.create_element("title")
+            .write_text_content(BytesText::new(&torrent.title))?;
+        w.create_element("guid")
+            .write_text_content(BytesText::new(&torrent.info_hash))?;
+        w.create_element("link")
+            .write_text_content(BytesText::new(&torrent.download))?;
+        if !torrent.info_page.is_empty() {
+            w.create_element("comments")
+                .write_text_content(BytesText::new(&torrent.info_page))?;
+        }
+        if let Some(pubdate) = &torrent.pubdate {
+            w.create_element("pubDate")
+                .write_text_content(BytesText::new(pubdate))?;
+        }
+        w.create_element("size")
+            .write_text_content(BytesText::new(&size))?;
+        w.create_element("enclosure")
+            .with_attribute(("url", torrent.download.as_str()))
+            .with_attribute(("length", size.as_str()))
+            .with_attribute(("type", "application/x-bittorrent"))
+            .write_empty()?;
+        if let Some(category) = torrent.category {
+            let id = category.torznab_id().to_string();
+            w.create_element("category")
+                .write_text_content(BytesText::new(&id))?;
+            attr(w, "category", &id)?;
+        }
+        if let Some(seeders) = torrent.seeders {
+            attr(w, "seeders", &seeders.to_string())?;
+            let peers = seeders + torrent.leechers.unwrap_or_default();
+            attr(w, "peers", &peers.to_string())?;
+        }
+        attr(w, "infohash", &torrent.info_hash)?;
+        attr(w, "magneturl", &torrent.download)?;
+        if let Some(imdb) = &torrent.imdb {
+            attr(w, "imdb", imdb.trim_start_matches("tt"))?;
+        }
+        let release = &torrent.release;
+        if let Some(year) = release.year {
+            attr(w, "year", &year.to_string())?;
+        }
+        if let Some(season) = release.seasons.first() {
+            attr(w, "season", &season.to_string())?;
+        }
+        if let Some(episode) = release.episodes.first() {
+            attr(w, "episode", &episode.to_string())?;
+        }
+        if let Some(resolution) = release.resolution {
+            attr(w, "resolution", resolution.name())?;
+        }
+        if let Some(codec) = release.video_codec {
+            attr(w, "video", codec.name())?;
+        }
+        if let Some(audio) = release.audio {
+            attr(w, "audio", audio.name())?;
+        }
+        if let Some(group) = &release.group {
+            attr(w, "team", group)?;
+        }
+        Ok::<_, quick_xml::Error>(())
+    })?;
+    Ok(())
+}
+
+fn attr(writer: &mut Writer<Vec<u8>>, name: &str, value: &str) -> Result<(), quick_xml::Error> {
+    writer
+        .create_element("torznab:attr")
+        .with_attribute(("name", name))
+        .with_attribute(("value", value))
+        .write_empty()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::{header, StatusCode};
+    use axum::response::IntoResponse;
+
+    use super::{caps, categories, feed, Function, TorznabError};
+    use crate::database::rarbg::category::Category;
+    use crate::database::rarbg::TorznabTorrent;
+    use crate::database::{SearchError, SourceFailure};
+    use crate::release::Release;
+
+    #[test]
+    fn maps_torznab_categories() {
+        assert_eq!(categories(Function::Search, None), None);
+        assert_eq!(
+            categories(Function::TvSearch, None),
+            Some(vec![
+                Category::TvEpisodes,
+                Category::TvHdEpisodes,
+                Category::TvUhdEpisodes
+            ])
+        );
+        assert_eq!(
+            categories(Function::Search, Some(&[5040, 3040])),
+            Some(vec![Category::TvHdEpisodes, Category::MusicFlac])
+        );
+        assert_eq!(categories(Function::Movie, Some(&[7000])), Some(vec![]));
+    }
+
+    #[test]
+    fn writes_feed() {
+        let torrent = TorznabTorrent {
+            title: "The.Matrix.1999.1080p.BluRay.x264-<GROUP>".to_string(),
+            info_hash: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            download: "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&dn=x"
+                .to_string(),
+            info_page: String::new(),
+            pubdate: Some("Mon, 01 Mar 2021 12:00:00 +0000".to_string()),
+            size: Some(1337),
+            category: Some(Category::MoviesX264_1080),
+            seeders: Some(5),
+            leechers: Some(2),
+            imdb: Some("tt0133093".to_string()),
+            release: Release::parse("The.Matrix.1999.1080p.BluRay.x264-<GROUP>"),
+        };
+
+        let failures = [SourceFailure {
+            source: "magnetico".to_string(),
+            error: "database is locked".to_string(),
+        }];
+        let xml = String::from_utf8(feed(&[torrent], &failures).unwrap()).unwrap();
+
+        assert!(xml.contains("<title>The.Matrix.1999.1080p.BluRay.x264-&lt;GROUP&gt;</title>"));
+        assert!(xml.contains("<pubDate>Mon, 01 Mar 2021 12:00:00 +0000</pubDate>"));
+        assert!(xml.contains("<category>2040</category>"));
+        assert!(xml.contains(r#"&amp;dn=x" length="1337" type="application/x-bittorrent"/>"#));
+        assert!(xml.contains(r#"<torznab:attr name="peers" value="7"/>"#));
+        assert!(xml.contains(r#"<torznab:attr name="imdb" value="0133093"/>"#));
+        assert!(xml.contains(r#"<torznab:attr name="year" value="1999"/>"#));
+        assert!(xml.contains(r#"<torznab:attr name="resolution" value="1080p"/>"#));
+        assert!(xml.contains(r#"<torznab:attr name="video" value="x264"/>"#));
+        assert!(!xml.contains(r#"name="season""#));
+        assert!(!xml.contains("<comments>"));
+        assert!(xml
+            .contains("<description>Failed sources: magnetico (database is locked)</description>"));
+        let xml = String::from_utf8(feed(&[], &[]).unwrap()).unwrap();
+        assert!(!xml.contains("<description>"));
+    }
+
+    #[test]
+    fn advertises_every_category() {
+        let all = Category::all().collect::<Vec<_>>();
+        let xml = String::from_utf8(caps(&all).unwrap()).unwrap();
+
+        for category in Category::all() {
+            assert!(
+                xml.contains(&format!(r#"id="{}""#, category.torznab_id())),
+                "{category:?} missing from {xml}"
+            );
+        }
+        assert!(
+            xml.contains(r#"<tv-search available="yes" supportedParams="q,tvdbid,season,ep"/>"#)
+        );
+
+        // Nothing the content filter hides
+        let safe = Category::all()
+            .filter(|c| *c != Category::XXX)
+            .collect::<Vec<_>>();
+        let xml = String::from_utf8(caps(&safe).unwrap()).unwrap();
+        assert!(!xml.contains(r#"id="6000""#), "{xml}");
+    }
+
+    #[test]
+    fn answers_errors_as_torznab() {
+        let missing = TorznabError::new(200, "Missing parameter (t)".to_string());
+        assert_eq!(missing.status, StatusCode::BAD_REQUEST);
+
+        let cursor = TorznabError::search(
+            SearchError::InvalidRequest("can't skip more than 2000 results".to_string()).into(),
+        );
+        assert_eq!(cursor.code, 201);
+        assert_eq!(cursor.status, StatusCode::BAD_REQUEST);
+
+        let unavailable = TorznabError::search(SearchError::Unavailable(Vec::new()).into());
+        assert_eq!(unavailable.code, 900);
+        assert_eq!(unavailable.status, StatusCode::BAD_GATEWAY);
+
+        let limited = TorznabError::rate_limited(Duration::from_millis(1500)).into_response();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers()[header::RETRY_AFTER], "2");
+    }
+}
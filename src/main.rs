@@ -1,15 +1,26 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
+use anyhow::Context;
 use axum::body::Body;
 use axum::http::{Request, StatusCode};
 use axum::response::IntoResponse;
 use axum::Router;
-use tracing::debug;
+use tracing::{debug, warn};
 
-use self::handler::{health, torrentsapi};
+use self::config::{Command, Config, PRIMARY_SOURCE};
+use self::database::dump::Dump;
+use self::database::federated::{Federation, Source};
+use self::database::filter::ContentFilter;
+use self::database::ids::parse_id_mappings;
+use self::database::rarbg::Database;
+use self::handler::{health, torrentsapi, torznab, AppState};
+use self::torrent::swarm::SwarmRefresher;
 
+mod config;
 mod database;
 mod handler;
+mod release;
 mod torrent;
 mod tvdb;
 
@@ -17,16 +28,73 @@ mod tvdb;
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
+    let (config, command) = Config::load()?;
+
+    let db = Database::connect(&config.database).await?;
+    match command {
+        Some(Command::Index) => {
+            let indexed = db.build_fts_index().await?;
+            println!("Indexed the titles of {} items", indexed);
+            return Ok(());
+        }
+        Some(Command::ImportIds { file }) => {
+            let csv = std::fs::read_to_string(&file)
+                .with_context(|| format!("reading {}", file.display()))?;
+            let imported = db.insert_id_mappings(&parse_id_mappings(&csv)?).await?;
+            println!("Imported {} id mappings", imported);
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if config.swarm.enabled {
+        match SwarmRefresher::new(db.clone(), &config.swarm).await {
+            Ok(refresher) => {
+                refresher.spawn();
+            }
+            Err(e) => warn!("Swarm stats won't be refreshed: {}", e),
+        }
+    } else {
+        warn!("swarm.enabled is off, sort=seeders/leechers list RARBG items newest first");
+    }
+
+    let db: Arc<dyn database::Database> = if config.sources.is_empty() {
+        Arc::new(db)
+    } else {
+        let mut sources = vec![Source {
+            name: PRIMARY_SOURCE.to_string(),
+            db: Arc::new(db),
+        }];
+        for source in &config.sources {
+            let dump = Dump::connect(source)
+                .await
+                .with_context(|| format!("opening source {}", source.name))?;
+            sources.push(Source {
+                name: source.name.clone(),
+                db: Arc::new(dump),
+            });
+        }
+        Arc::new(Federation::new(sources))
+    };
+    let db: Arc<dyn database::Database> = if config.content_filter.is_empty() {
+        db
+    } else {
+        Arc::new(ContentFilter::new(db, &config.content_filter)?)
+    };
+
+    let state = AppState::new(&config, db)?;
     let app = Router::new()
-        .nest("/health", health::router())
-        .nest("/", torrentsapi::router().await?)
+        .nest("/health", health::router(state.clone()))
+        .nest("/", torrentsapi::router(state.clone()))
+        .merge(torznab::router(state))
         .fallback(handle_not_found);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
+    let addr = config.listen;
     debug!("Listening on {:?}", addr);
 
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        // Requests without a token are rate limited by IP
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
 
     Ok(())
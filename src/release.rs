@@ -0,0 +1,1260 @@
+//! Scene release names like `Show.Name.S02E05.1080p.WEB.h264-GROUP`, taken
+//! apart into what they tell about the release.
+//!
+//! A name is a title, optionally a year, then markers in no fixed order, and
+//! the group after the last dash. Markers are only looked for after the
+//! title, so `Charlottes.Web.2006.DVDRip` is not a web release.
+
+use serde::{Deserialize, Serialize};
+
+/// An enum written as the given names, in responses and query parameters alike
+macro_rules! named {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident => $text:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+        pub enum $name {
+            $(#[serde(rename = $text)] $variant,)*
+        }
+
+        // NOTE: allow(dead_code) rationale: Not every enum is written out
+        // outside of serde
+        #[allow(dead_code)]
+        impl $name {
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $text,)*
+                }
+            }
+        }
+    };
+}
+
+named!(Resolution {
+    R480p => "480p",
+    R576p => "576p",
+    R720p => "720p",
+    R1080p => "1080p",
+    R2160p => "2160p",
+});
+
+named!(Source {
+    Cam => "cam",
+    Telesync => "telesync",
+    Screener => "screener",
+    Dvd => "dvd",
+    Hdtv => "hdtv",
+    WebRip => "webrip",
+    Web => "web",
+    BluRay => "bluray",
+    Remux => "remux",
+});
+
+named!(VideoCodec {
+    Xvid => "xvid",
+    X264 => "x264",
+    X265 => "x265",
+    Av1 => "av1",
+    Vc1 => "vc1",
+    Mpeg2 => "mpeg2",
+});
+
+named!(Audio {
+    Mp3 => "mp3",
+    Aac => "aac",
+    Ac3 => "ac3",
+    Eac3 => "eac3",
+    Dts => "dts",
+    DtsHd => "dtshd",
+    DtsX => "dtsx",
+    TrueHd => "truehd",
+    Flac => "flac",
+    Opus => "opus",
+});
+
+named!(HdrFormat {
+    Hdr => "hdr",
+    Hdr10 => "hdr10",
+    Hdr10Plus => "hdr10plus",
+    DolbyVision => "dolbyvision",
+});
+
+// Aliases are lowercase words, as `tokenize` splits them
+
+const RESOLUTIONS: &[(&str, Resolution)] = &[
+    ("2160p", Resolution::R2160p),
+    ("4k", Resolution::R2160p),
+    ("uhd", Resolution::R2160p),
+    ("3840x2160", Resolution::R2160p),
+    ("1080p", Resolution::R1080p),
+    ("1080i", Resolution::R1080p),
+    ("1920x1080", Resolution::R1080p),
+    ("720p", Resolution::R720p),
+    ("1280x720", Resolution::R720p),
+    ("576p", Resolution::R576p),
+    ("576i", Resolution::R576p),
+    ("480p", Resolution::R480p),
+    ("480i", Resolution::R480p),
+];
+
+const SOURCES: &[(&str, Source)] = &[
+    ("cam", Source::Cam),
+    ("camrip", Source::Cam),
+    ("hdcam", Source::Cam),
+    ("telesync", Source::Telesync),
+    ("hdts", Source::Telesync),
+    ("screener", Source::Screener),
+    ("dvdscr", Source::Screener),
+    ("scr", Source::Screener),
+    ("dvd", Source::Dvd),
+    ("dvdrip", Source::Dvd),
+    ("dvdr", Source::Dvd),
+    ("dvd5", Source::Dvd),
+    ("dvd9", Source::Dvd),
+    ("hdtv", Source::Hdtv),
+    ("hdtvrip", Source::Hdtv),
+    ("pdtv", Source::Hdtv),
+    ("sdtv", Source::Hdtv),
+    ("web rip", Source::WebRip),
+    ("webrip", Source::WebRip),
+    ("web", Source::Web),
+    ("web dl", Source::Web),
+    ("webdl", Source::Web),
+    ("blu ray", Source::BluRay),
+    ("bluray", Source::BluRay),
+    ("bdrip", Source::BluRay),
+    ("brrip", Source::BluRay),
+    ("remux", Source::Remux),
+    ("bdremux", Source::Remux),
+];
+
+const VIDEO_CODECS: &[(&str, VideoCodec)] = &[
+    ("xvid", VideoCodec::Xvid),
+    ("divx", VideoCodec::Xvid),
+    ("x264", VideoCodec::X264),
+    ("h264", VideoCodec::X264),
+    ("h 264", VideoCodec::X264),
+    ("avc", VideoCodec::X264),
+    ("x265", VideoCodec::X265),
+    ("h265", VideoCodec::X265),
+    ("h 265", VideoCodec::X265),
+    ("hevc", VideoCodec::X265),
+    ("av1", VideoCodec::Av1),
+    ("vc1", VideoCodec::Vc1),
+    ("vc 1", VideoCodec::Vc1),
+    ("mpeg2", VideoCodec::Mpeg2),
+];
+
+/// Channel layouts like `DD5.1` split into `dd5` and `1`
+const AUDIO: &[(&str, Audio)] = &[
+    ("mp3", Audio::Mp3),
+    ("aac", Audio::Aac),
+    ("aac2", Audio::Aac),
+    ("aac5", Audio::Aac),
+    ("ac3", Audio::Ac3),
+    ("dd", Audio::Ac3),
+    ("dd2", Audio::Ac3),
+    ("dd5", Audio::Ac3),
+    ("eac3", Audio::Eac3),
+    ("ddp", Audio::Eac3),
+    ("ddp2", Audio::Eac3),
+    ("ddp5", Audio::Eac3),
+    ("ddp7", Audio::Eac3),
+    ("dd+", Audio::Eac3),
+    ("dd+2", Audio::Eac3),
+    ("dd+5", Audio::Eac3),
+    ("dd+7", Audio::Eac3),
+    ("dts", Audio::Dts),
+    ("dts hd", Audio::DtsHd),
+    ("dtshd", Audio::DtsHd),
+    ("dts x", Audio::DtsX),
+    ("dtsx", Audio::DtsX),
+    ("truehd", Audio::TrueHd),
+    ("flac", Audio::Flac),
+    ("opus", Audio::Opus),
+];
+
+const HDR: &[(&str, HdrFormat)] = &[
+    ("hdr", HdrFormat::Hdr),
+    ("hdr10", HdrFormat::Hdr10),
+    ("hdr10+", HdrFormat::Hdr10Plus),
+    ("hdr10plus", HdrFormat::Hdr10Plus),
+    ("dv", HdrFormat::DolbyVision),
+    ("dovi", HdrFormat::DolbyVision),
+    ("dolbyvision", HdrFormat::DolbyVision),
+    ("dolby vision", HdrFormat::DolbyVision),
+];
+
+/// Markers that are also common title words. They don't end the title, but
+/// are picked up after it.
+const AMBIGUOUS: &[&str] = &["cam", "scr", "dvd", "web", "uhd", "4k", "avc"];
+
+/// Words that are never part of a title, without telling anything we keep
+const TITLE_ENDS: &[&str] = &[
+    "proper",
+    "repack",
+    "rerip",
+    "internal",
+    "limited",
+    "extended",
+    "unrated",
+    "uncut",
+    "remastered",
+    "complete",
+];
+
+/// Dash suffixes that are the end of a marker rather than a group
+const NOT_GROUPS: &[&str] = &["dl", "hd", "ray", "rip", "x", "ma"];
+
+const EXTENSIONS: &[&str] = &["mkv", "mp4", "avi", "m4v", "wmv"];
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Release {
+    pub title: String,
+    pub year: Option<u16>,
+    /// Several for multi-season packs
+    pub seasons: Vec<u16>,
+    /// Empty for season packs, several for multi-episode releases
+    pub episodes: Vec<u16>,
+    pub resolution: Option<Resolution>,
+    pub source: Option<Source>,
+    pub video_codec: Option<VideoCodec>,
+    pub audio: Option<Audio>,
+    pub hdr: Vec<HdrFormat>,
+    pub group: Option<String>,
+    pub proper: bool,
+    pub repack: bool,
+}
+
+/// A word of the name, and whether a dash came right before it
+#[derive(Debug)]
+struct Token<'a> {
+    text: &'a str,
+    lower: String,
+    after_dash: bool,
+}
+
+impl Release {
+    pub fn parse(name: &str) -> Self {
+        let name = name.trim();
+        // Fansub style: `[Group] Title - 01 [1080p].mkv`
+        let (prefix_group, name) = match name.strip_prefix('[').and_then(|n| n.split_once(']')) {
+            Some((group, rest)) if !group.trim().is_empty() => (Some(group.trim()), rest),
+            _ => (None, name),
+        };
+        let (core, tags) = split_tags(name);
+        let (base, suffix_group) = split_group(core);
+
+        let mut tokens = tokenize(base);
+        let mut group = suffix_group.or(prefix_group);
+        let (mut title_end, mut year) = find_title_end(&tokens);
+        if title_end == tokens.len() && year.is_none() && suffix_group.is_some() {
+            // Nothing but a title, like `Spider-Man`
+            tokens = tokenize(core);
+            group = prefix_group;
+            (title_end, year) = (tokens.len(), None);
+        }
+
+        let mut release = Release {
+            year,
+            group: group.map(str::to_string),
+            ..Default::default()
+        };
+        if prefix_group.is_some() && release.absolute_episode(&tokens[..title_end]) {
+            title_end -= 1;
+        }
+        release.title = tokens[..title_end]
+            .iter()
+            .map(|token| token.text)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        tokens.extend(tokenize(tags));
+        release.scan_markers(&tokens[title_end..]);
+        release
+    }
+
+    /// Fansubs number episodes from the start of the show: `Title - 01`
+    fn absolute_episode(&mut self, title: &[Token]) -> bool {
+        match title {
+            [_, .., last] if last.after_dash => match number(&last.lower, 4) {
+                Some(episode) => {
+                    self.episodes.push(episode);
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Fills in everything the tokens following the title tell
+    fn scan_markers(&mut self, tokens: &[Token]) {
+        let mut i = 0;
+        while i < tokens.len() {
+            let rest = &tokens[i..];
+            i += if let Some((seasons, episodes, n)) = episode_marker(rest) {
+                if self.seasons.is_empty() && self.episodes.is_empty() {
+                    self.seasons = seasons;
+                    self.episodes = episodes;
+                }
+                n
+            } else if let Some((resolution, n)) = lookup(RESOLUTIONS, rest) {
+                self.resolution.get_or_insert(resolution);
+                n
+            } else if let Some((source, n)) = lookup(SOURCES, rest) {
+                // `BluRay.REMUX` is a remux
+                if source == Source::Remux || self.source.is_none() {
+                    self.source = Some(source);
+                }
+                n
+            } else if let Some((codec, n)) = lookup(VIDEO_CODECS, rest) {
+                self.video_codec.get_or_insert(codec);
+                n
+            } else if let Some((audio, n)) = lookup(AUDIO, rest) {
+                self.audio.get_or_insert(audio);
+                n
+            } else if let Some((hdr, n)) = lookup(HDR, rest) {
+                if !self.hdr.contains(&hdr) {
+                    self.hdr.push(hdr);
+                }
+                n
+            } else {
+                match rest[0].lower.as_str() {
+                    "proper" => self.proper = true,
+                    "repack" | "rerip" => self.repack = true,
+                    _ => {}
+                }
+                1
+            };
+        }
+    }
+}
+
+/// Splits off trailing `[site]` tags and the file extension
+fn split_tags(name: &str) -> (&str, &str) {
+    let mut core = name.trim_end();
+    loop {
+        if let Some((base, ext)) = core.rsplit_once('.') {
+            if EXTENSIONS.contains(&ext.to_lowercase().as_str()) {
+                core = base.trim_end();
+                continue;
+            }
+        }
+        match core.strip_suffix(']').and_then(|c| c.rfind('[')) {
+            Some(start) if start > 0 => core = core[..start].trim_end(),
+            _ => break,
+        }
+    }
+    (core, &name[core.len()..])
+}
+
+/// Splits off the `-GROUP` at the end
+fn split_group(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once('-') {
+        Some((base, group))
+            if !base.is_empty()
+                && !group.is_empty()
+                && group.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !group.chars().all(|c| c.is_ascii_digit())
+                && !NOT_GROUPS.contains(&group.to_lowercase().as_str())
+                && season_episodes(&group.to_lowercase()).is_none() =>
+        {
+            (base, Some(group))
+        }
+        _ => (name, None),
+    }
+}
+
+fn tokenize(name: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut after_dash = false;
+    for (i, c) in name.char_indices().chain([(name.len(), ' ')]) {
+        if !matches!(
+            c,
+            '.' | ' ' | '_' | '-' | '[' | ']' | '(' | ')' | '{' | '}' | ','
+        ) {
+            continue;
+        }
+        if start < i {
+            let text = &name[start..i];
+            tokens.push(Token {
+                text,
+                lower: text.to_lowercase(),
+                after_dash,
+            });
+            after_dash = false;
+        }
+        after_dash |= c == '-';
+        start = i + c.len_utf8();
+    }
+    tokens
+}
+
+/// Where the title stops, and the year right after it if there is one. The
+/// first word always belongs to the title.
+fn find_title_end(tokens: &[Token]) -> (usize, Option<u16>) {
+    let marker = (1..tokens.len()).find(|&i| ends_title(&tokens[i..]));
+    let end = marker.unwrap_or(tokens.len());
+
+    // The last year wins: `Blade.Runner.2049.2017.1080p`
+    if let Some((i, year)) = (1..end)
+        .rev()
+        .find_map(|i| year(&tokens[i].lower).map(|year| (i, year)))
+    {
+        return (i, Some(year));
+    }
+    if marker.is_none() {
+        return (end, None);
+    }
+
+    // `Show.Name.WEB.x264`: ambiguous markers right before a certain one
+    let mut end = end;
+    while end > 1 && AMBIGUOUS.contains(&tokens[end - 1].lower.as_str()) {
+        end -= 1;
+    }
+    (end, None)
+}
+
+fn ends_title(tokens: &[Token]) -> bool {
+    let word = tokens[0].lower.as_str();
+    // `WEB-DL` is certain, `WEB` isn't
+    let certain = |n: usize| n > 1 || !AMBIGUOUS.contains(&word);
+    let markers = [
+        lookup(RESOLUTIONS, tokens).map(|(_, n)| n),
+        lookup(SOURCES, tokens).map(|(_, n)| n),
+        lookup(VIDEO_CODECS, tokens).map(|(_, n)| n),
+    ];
+    episode_marker(tokens).is_some()
+        || markers.into_iter().flatten().any(certain)
+        || TITLE_ENDS.contains(&word)
+}
+
+/// The value of the longest alias `tokens` start with, and how many tokens
+/// it covers
+fn lookup<T: Copy>(table: &[(&str, T)], tokens: &[Token]) -> Option<(T, usize)> {
+    table
+        .iter()
+        .filter_map(|&(alias, value)| {
+            let words = alias.split(' ').collect::<Vec<_>>();
+            let matches =
+                tokens.len() >= words.len() && words.iter().zip(tokens).all(|(w, t)| t.lower == *w);
+            matches.then_some((value, words.len()))
+        })
+        .max_by_key(|&(_, n)| n)
+}
+
+/// Seasons and episodes of `S01`, `S01E02`, `S01E02E03`, `S01E02-E04`,
+/// `S01E02-04`, `S01-S03`, `1x02` or `Season 1`, and how many tokens they
+/// cover
+fn episode_marker(tokens: &[Token]) -> Option<(Vec<u16>, Vec<u16>, usize)> {
+    let first = tokens[0].lower.as_str();
+    let next = tokens.get(1);
+
+    if first == "season" {
+        let season = number(&next?.lower, 3)?;
+        return Some((vec![season], vec![], 2));
+    }
+    if let Some((season, episode)) = first.split_once('x') {
+        let (season, episode) = (number(season, 2)?, number(episode, 3)?);
+        return Some((vec![season], vec![episode], 1));
+    }
+
+    let (season, mut episodes) = season_episodes(first)?;
+    let next = next.filter(|t| t.after_dash).map(|t| t.lower.as_str());
+    match (episodes.last().copied(), next) {
+        (Some(start), Some(next)) => {
+            if let Some(end) = number(next.strip_prefix('e').unwrap_or(next), 3) {
+                episodes.extend(range(start, end).skip(1));
+                return Some((vec![season], episodes, 2));
+            }
+        }
+        (None, Some(next)) => {
+            if let Some(end) = next.strip_prefix('s').and_then(|n| number(n, 3)) {
+                return Some((range(season, end).collect(), episodes, 2));
+            }
+        }
+        _ => {}
+    }
+    Some((vec![season], episodes, 1))
+}
+
+/// `s01e02e03` into its season and episodes
+fn season_episodes(word: &str) -> Option<(u16, Vec<u16>)> {
+    let rest = word.strip_prefix('s')?;
+    let (season, episodes) = match rest.find('e') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+    let season = number(season, 4)?;
+    let episodes = match episodes {
+        Some(episodes) => episodes
+            .split('e')
+            .map(|episode| number(episode, 4))
+            .collect::<Option<Vec<_>>>()?,
+        None => vec![],
+    };
+    Some((season, episodes))
+}
+
+/// `start..=end`, or just `start` if that isn't a sensible range
+fn range(start: u16, end: u16) -> impl Iterator<Item = u16> {
+    let end = if end > start && end - start < 100 {
+        end
+    } else {
+        start
+    };
+    start..=end
+}
+
+fn number(digits: &str, max_len: usize) -> Option<u16> {
+    if digits.is_empty() || digits.len() > max_len || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn year(word: &str) -> Option<u16> {
+    number(word, 4).filter(|year| (1900..=2099).contains(year))
+}
+
+/// Query parameters narrowing results down by what their names tell
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReleaseFilter {
+    pub resolution: Option<Resolution>,
+    pub source: Option<Source>,
+    pub video_codec: Option<VideoCodec>,
+    pub group: Option<String>,
+    /// `false` asks for SDR releases only
+    pub hdr: Option<bool>,
+    /// Episodes of the season, and packs including it
+    pub season: Option<u16>,
+    /// The episode, multi-episode releases including it, and season packs
+    pub episode: Option<u16>,
+}
+
+impl ReleaseFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn matches(&self, release: &Release) -> bool {
+        self.resolution
+            .is_none_or(|r| release.resolution == Some(r))
+            && self.source.is_none_or(|s| release.source == Some(s))
+            && self
+                .video_codec
+                .is_none_or(|c| release.video_codec == Some(c))
+            && self.group.as_ref().is_none_or(|group| {
+                release
+                    .group
+                    .as_ref()
+                    .is_some_and(|g| g.eq_ignore_ascii_case(group))
+            })
+            && self.hdr.is_none_or(|hdr| hdr != release.hdr.is_empty())
+            && self.season.is_none_or(|s| release.seasons.contains(&s))
+            && self.episode.is_none_or(|e| {
+                release.episodes.contains(&e)
+                    || (release.episodes.is_empty() && !release.seasons.is_empty())
+            })
+    }
+
+    /// `search_string` terms finding every matching title, and hopefully few
+    /// more, so the database does the bulk of the filtering
+    pub fn search_terms(&self) -> String {
+        let mut terms = Vec::new();
+        if let Some(resolution) = self.resolution {
+            terms.push(alternatives(RESOLUTIONS, resolution));
+        }
+        if let Some(source) = self.source {
+            terms.push(alternatives(SOURCES, source));
+        }
+        if let Some(codec) = self.video_codec {
+            terms.push(alternatives(VIDEO_CODECS, codec));
+        }
+        if let Some(group) = &self.group {
+            terms.push(format!("\"{}\"", group.replace('"', " ")));
+        }
+        // Multi-season packs like `S01-S03` only show up if they name the
+        // season, ranges can't be searched for
+        if let Some(season) = self.season {
+            terms.push(format!(
+                "s{season:02}*|s{season}e*|\"s{season}\"|\"season {season}\"|{season}x*|{season:02}x*"
+            ));
+        }
+        if self.hdr == Some(true) {
+            let all = HDR.iter().map(|&(alias, _)| format!("\"{alias}\""));
+            terms.push(all.collect::<Vec<_>>().join("|"));
+        }
+        terms.join(" ")
+    }
+}
+
+/// Every alias of `value` as `search_string` alternatives
+fn alternatives<T: Copy + PartialEq>(table: &[(&str, T)], value: T) -> String {
+    table
+        .iter()
+        .filter(|&&(_, v)| v == value)
+        .map(|&(alias, _)| format!("\"{alias}\""))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Audio, HdrFormat, Release, ReleaseFilter, Resolution as R, Source as S, VideoCodec as V,
+    };
+
+    /// A name and what should be parsed out of it. Booleans and absent
+    /// fields are left at their defaults.
+    fn release(title: &str, f: impl FnOnce(&mut Release)) -> Release {
+        let mut release = Release {
+            title: title.to_string(),
+            ..Default::default()
+        };
+        f(&mut release);
+        release
+    }
+
+    fn group(group: &str) -> Option<String> {
+        Some(group.to_string())
+    }
+
+    #[test]
+    fn parses_release_names() {
+        let cases = [
+            (
+                "Show.Name.S02E05.1080p.WEB.h264-GROUP",
+                release("Show Name", |r| {
+                    r.seasons = vec![2];
+                    r.episodes = vec![5];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GROUP");
+                }),
+            ),
+            (
+                "The.Matrix.1999.1080p.BluRay.x264-SiNNERS",
+                release("The Matrix", |r| {
+                    r.year = Some(1999);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("SiNNERS");
+                }),
+            ),
+            (
+                "The.Office.US.S05E14.720p.HDTV.x264-CTU[rartv]",
+                release("The Office US", |r| {
+                    r.seasons = vec![5];
+                    r.episodes = vec![14];
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::Hdtv);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("CTU");
+                }),
+            ),
+            (
+                "Doctor.Who.2005.S13E01.1080p.AMZN.WEB-DL.DDP5.1.H.264-NTb",
+                release("Doctor Who", |r| {
+                    r.year = Some(2005);
+                    r.seasons = vec![13];
+                    r.episodes = vec![1];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::Eac3);
+                    r.group = group("NTb");
+                }),
+            ),
+            (
+                "Show.S01E01E02.720p.WEBRip.x265-GRP",
+                release("Show", |r| {
+                    r.seasons = vec![1];
+                    r.episodes = vec![1, 2];
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::WebRip);
+                    r.video_codec = Some(V::X265);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show.S01E01-E03.1080p.WEB-Rip.HEVC-GRP",
+                release("Show", |r| {
+                    r.seasons = vec![1];
+                    r.episodes = vec![1, 2, 3];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::WebRip);
+                    r.video_codec = Some(V::X265);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show.S03E08-09.HDTV.XviD-LOL",
+                release("Show", |r| {
+                    r.seasons = vec![3];
+                    r.episodes = vec![8, 9];
+                    r.source = Some(S::Hdtv);
+                    r.video_codec = Some(V::Xvid);
+                    r.group = group("LOL");
+                }),
+            ),
+            (
+                "Show.Name.S01.1080p.BluRay.x264-ROVERS",
+                release("Show Name", |r| {
+                    r.seasons = vec![1];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("ROVERS");
+                }),
+            ),
+            (
+                "Show.Name.S01-S03.COMPLETE.720p.WEB-DL.x264-GRP",
+                release("Show Name", |r| {
+                    r.seasons = vec![1, 2, 3];
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show Name Season 2 Complete 720p",
+                release("Show Name", |r| {
+                    r.seasons = vec![2];
+                    r.resolution = Some(R::R720p);
+                }),
+            ),
+            (
+                "Show.Name.3x07.HDTV.XviD-FQM",
+                release("Show Name", |r| {
+                    r.seasons = vec![3];
+                    r.episodes = vec![7];
+                    r.source = Some(S::Hdtv);
+                    r.video_codec = Some(V::Xvid);
+                    r.group = group("FQM");
+                }),
+            ),
+            (
+                "Blade.Runner.2049.2017.2160p.UHD.BluRay.REMUX.HDR.HEVC.Atmos-EPSiLON",
+                release("Blade Runner 2049", |r| {
+                    r.year = Some(2017);
+                    r.resolution = Some(R::R2160p);
+                    r.source = Some(S::Remux);
+                    r.video_codec = Some(V::X265);
+                    r.hdr = vec![HdrFormat::Hdr];
+                    r.group = group("EPSiLON");
+                }),
+            ),
+            (
+                "2001.A.Space.Odyssey.1968.1080p.BluRay.x264-AMIABLE",
+                release("2001 A Space Odyssey", |r| {
+                    r.year = Some(1968);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("AMIABLE");
+                }),
+            ),
+            (
+                "Charlottes.Web.2006.DVDRip.XviD-DiAMOND",
+                release("Charlottes Web", |r| {
+                    r.year = Some(2006);
+                    r.source = Some(S::Dvd);
+                    r.video_codec = Some(V::Xvid);
+                    r.group = group("DiAMOND");
+                }),
+            ),
+            (
+                "Show.Name.WEB.x264-TBS",
+                release("Show Name", |r| {
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("TBS");
+                }),
+            ),
+            (
+                "Movie.2019.PROPER.1080p.WEB.H264-CMRG",
+                release("Movie", |r| {
+                    r.year = Some(2019);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("CMRG");
+                    r.proper = true;
+                }),
+            ),
+            (
+                "Show.S02E03.REPACK.720p.HDTV.x264-KILLERS",
+                release("Show", |r| {
+                    r.seasons = vec![2];
+                    r.episodes = vec![3];
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::Hdtv);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("KILLERS");
+                    r.repack = true;
+                }),
+            ),
+            (
+                "Movie.Name.2021.2160p.WEB-DL.DDP5.1.Atmos.DV.HDR10.H.265-FLUX",
+                release("Movie Name", |r| {
+                    r.year = Some(2021);
+                    r.resolution = Some(R::R2160p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X265);
+                    r.audio = Some(Audio::Eac3);
+                    r.hdr = vec![HdrFormat::DolbyVision, HdrFormat::Hdr10];
+                    r.group = group("FLUX");
+                }),
+            ),
+            (
+                "Movie.2020.2160p.UHD.BluRay.x265.10bit.HDR10+.TrueHD.7.1-GRP",
+                release("Movie", |r| {
+                    r.year = Some(2020);
+                    r.resolution = Some(R::R2160p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X265);
+                    r.audio = Some(Audio::TrueHd);
+                    r.hdr = vec![HdrFormat::Hdr10Plus];
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.1995.1080p.BluRay.DTS-HD.MA.5.1.x264-GRP",
+                release("Movie", |r| {
+                    r.year = Some(1995);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::DtsHd);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.1995.1080p.BluRay.x264.DTS-X",
+                release("Movie", |r| {
+                    r.year = Some(1995);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::DtsX);
+                }),
+            ),
+            (
+                "Movie.1995.1080p.BluRay.x264.DTS-HD",
+                release("Movie", |r| {
+                    r.year = Some(1995);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::DtsHd);
+                }),
+            ),
+            (
+                "Movie.2008.720p.BRRip.AAC2.0.x264-GRP",
+                release("Movie", |r| {
+                    r.year = Some(2008);
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::Aac);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.2008.DVDRip.AC3.XviD-GRP",
+                release("Movie", |r| {
+                    r.year = Some(2008);
+                    r.source = Some(S::Dvd);
+                    r.video_codec = Some(V::Xvid);
+                    r.audio = Some(Audio::Ac3);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie 2019 HDCAM x264 MP3",
+                release("Movie", |r| {
+                    r.year = Some(2019);
+                    r.source = Some(S::Cam);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::Mp3);
+                }),
+            ),
+            (
+                "Movie.2019.HDTS.x264-GRP",
+                release("Movie", |r| {
+                    r.year = Some(2019);
+                    r.source = Some(S::Telesync);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.2019.DVDScr.XviD-GRP",
+                release("Movie", |r| {
+                    r.year = Some(2019);
+                    r.source = Some(S::Screener);
+                    r.video_codec = Some(V::Xvid);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.2012.BDRemux.1080p.AVC.FLAC-GRP",
+                release("Movie", |r| {
+                    r.year = Some(2012);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Remux);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::Flac);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.2012.1080p.BluRay.VC-1.DD5.1-GRP",
+                release("Movie", |r| {
+                    r.year = Some(2012);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::Vc1);
+                    r.audio = Some(Audio::Ac3);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.2022.1080p.WEB.AV1.Opus-GRP",
+                release("Movie", |r| {
+                    r.year = Some(2022);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::Av1);
+                    r.audio = Some(Audio::Opus);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.1999.DVD9.MPEG2-GRP",
+                release("Movie", |r| {
+                    r.year = Some(1999);
+                    r.source = Some(S::Dvd);
+                    r.video_codec = Some(V::Mpeg2);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.1999.576p.PDTV.x264-GRP",
+                release("Movie", |r| {
+                    r.year = Some(1999);
+                    r.resolution = Some(R::R576p);
+                    r.source = Some(S::Hdtv);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show.S01E01.480p.x264-mSD",
+                release("Show", |r| {
+                    r.seasons = vec![1];
+                    r.episodes = vec![1];
+                    r.resolution = Some(R::R480p);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("mSD");
+                }),
+            ),
+            (
+                "Show S01E01 1920x1080 AVC",
+                release("Show", |r| {
+                    r.seasons = vec![1];
+                    r.episodes = vec![1];
+                    r.resolution = Some(R::R1080p);
+                    r.video_codec = Some(V::X264);
+                }),
+            ),
+            (
+                "Movie.Name.2160p.4K.WEB.x265-GRP",
+                release("Movie Name", |r| {
+                    r.resolution = Some(R::R2160p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X265);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.Name.(2010).1080p.BluRay.x264.mkv",
+                release("Movie Name", |r| {
+                    r.year = Some(2010);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                }),
+            ),
+            (
+                "Movie Name (2010) [1080p] [BluRay] [YTS.MX]",
+                release("Movie Name", |r| {
+                    r.year = Some(2010);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                }),
+            ),
+            (
+                "Show_Name_S04E12_720p_WEB-DL_AAC2.0_H.264-GRP.mkv",
+                release("Show Name", |r| {
+                    r.seasons = vec![4];
+                    r.episodes = vec![12];
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::Aac);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show.Name.S10E100.1080p.WEB.h264-GRP",
+                release("Show Name", |r| {
+                    r.seasons = vec![10];
+                    r.episodes = vec![100];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show.Name.2021.03.01.Guest.Name.720p.WEB.h264-GRP",
+                release("Show Name", |r| {
+                    r.year = Some(2021);
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.Name.2015.EXTENDED.1080p.BluRay.x264-GRP",
+                release("Movie Name", |r| {
+                    r.year = Some(2015);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.Name.UNRATED.DVDRip.XviD-GRP",
+                release("Movie Name", |r| {
+                    r.source = Some(S::Dvd);
+                    r.video_codec = Some(V::Xvid);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show.Name.S05E03.iNTERNAL.1080p.WEB.h264-GRP",
+                release("Show Name", |r| {
+                    r.seasons = vec![5];
+                    r.episodes = vec![3];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show.Name.S05E03.RERIP.720p.HDTV.x264-GRP",
+                release("Show Name", |r| {
+                    r.seasons = vec![5];
+                    r.episodes = vec![3];
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::Hdtv);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                    r.repack = true;
+                }),
+            ),
+            (
+                "Marvels.Agents.of.S.H.I.E.L.D.S07E01.720p.HDTV.x264-AVS",
+                release("Marvels Agents of S H I E L D", |r| {
+                    r.seasons = vec![7];
+                    r.episodes = vec![1];
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::Hdtv);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("AVS");
+                }),
+            ),
+            (
+                "X-Men.Days.of.Future.Past.2014.1080p.BluRay.x264-SPARKS",
+                release("X Men Days of Future Past", |r| {
+                    r.year = Some(2014);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("SPARKS");
+                }),
+            ),
+            (
+                "9-1-1.S04E01.720p.HDTV.x264-SYNCOPY",
+                release("9 1 1", |r| {
+                    r.seasons = vec![4];
+                    r.episodes = vec![1];
+                    r.resolution = Some(R::R720p);
+                    r.source = Some(S::Hdtv);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("SYNCOPY");
+                }),
+            ),
+            (
+                "1923.S01E01.1080p.WEB.h264-GRP",
+                release("1923", |r| {
+                    r.seasons = vec![1];
+                    r.episodes = vec![1];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Cam.2018.1080p.NF.WEB-DL.DD5.1.x264-NTG",
+                release("Cam", |r| {
+                    r.year = Some(2018);
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.audio = Some(Audio::Ac3);
+                    r.group = group("NTG");
+                }),
+            ),
+            ("Spider-Man", release("Spider Man", |_| {})),
+            ("Just a title", release("Just a title", |_| {})),
+            ("", release("", |_| {})),
+            (
+                "[SubsPlease] Show Name - 07 (1080p) [A1B2C3D4].mkv",
+                release("Show Name", |r| {
+                    r.episodes = vec![7];
+                    r.resolution = Some(R::R1080p);
+                    r.group = group("SubsPlease");
+                }),
+            ),
+            (
+                "Show.Name.S2023E05.1080p.WEB.h264-GRP",
+                release("Show Name", |r| {
+                    r.seasons = vec![2023];
+                    r.episodes = vec![5];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Show.Name.S01E02.Episode.Title.1080p.WEB.h264-GRP",
+                release("Show Name", |r| {
+                    r.seasons = vec![1];
+                    r.episodes = vec![2];
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::Web);
+                    r.video_codec = Some(V::X264);
+                    r.group = group("GRP");
+                }),
+            ),
+            (
+                "Movie.Name.1080p.Blu-Ray.DolbyVision.x265",
+                release("Movie Name", |r| {
+                    r.resolution = Some(R::R1080p);
+                    r.source = Some(S::BluRay);
+                    r.video_codec = Some(V::X265);
+                    r.hdr = vec![HdrFormat::DolbyVision];
+                }),
+            ),
+        ];
+
+        for (name, expected) in cases {
+            assert_eq!(Release::parse(name), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn filters_releases() {
+        let release = Release::parse("Movie.2021.2160p.WEB-DL.DV.H.265-FLUX");
+        let filter = |f: fn(&mut ReleaseFilter)| {
+            let mut filter = ReleaseFilter::default();
+            f(&mut filter);
+            filter.matches(&release)
+        };
+
+        assert!(filter(|_| {}));
+        assert!(filter(|f| f.resolution = Some(R::R2160p)));
+        assert!(!filter(|f| f.resolution = Some(R::R1080p)));
+        assert!(filter(|f| f.source = Some(S::Web)));
+        assert!(!filter(|f| f.source = Some(S::WebRip)));
+        assert!(filter(|f| f.video_codec = Some(V::X265)));
+        assert!(filter(|f| f.group = Some("flux".to_string())));
+        assert!(!filter(|f| f.group = Some("NTb".to_string())));
+        assert!(filter(|f| f.hdr = Some(true)));
+        assert!(!filter(|f| f.hdr = Some(false)));
+        assert!(!filter(|f| f.season = Some(1)));
+    }
+
+    #[test]
+    fn filters_seasons_and_episodes() {
+        let cases: &[(&str, Option<u16>, Option<u16>, bool)] = &[
+            ("Show.S02E05.720p", Some(2), None, true),
+            ("Show.S02E05.720p", Some(2), Some(5), true),
+            ("Show.S02E05.720p", Some(2), Some(6), false),
+            ("Show.S02E05.720p", Some(3), Some(5), false),
+            ("Show.S02E04E05.720p", Some(2), Some(5), true),
+            ("Show.S02E04-E06.720p", Some(2), Some(5), true),
+            ("Show.S02E04-E06.720p", Some(2), Some(7), false),
+            ("Show.S02.720p", Some(2), None, true),
+            ("Show.S02.720p", Some(2), Some(5), true),
+            ("Show.S01-S03.720p", Some(2), Some(5), true),
+            ("Show.S01-S03.720p", Some(4), None, false),
+            ("Show.2x05.HDTV", Some(2), Some(5), true),
+            ("[Fansub] Show - 05 [720p]", None, Some(5), true),
+            ("Movie.2019.720p", None, Some(5), false),
+            ("Movie.2019.720p", Some(1), None, false),
+        ];
+
+        for &(name, season, episode, expected) in cases {
+            let filter = ReleaseFilter {
+                season,
+                episode,
+                ..Default::default()
+            };
+            assert_eq!(
+                filter.matches(&Release::parse(name)),
+                expected,
+                "{name} {season:?}x{episode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn filters_compile_to_search_terms() {
+        assert_eq!(ReleaseFilter::default().search_terms(), "");
+
+        let filter = ReleaseFilter {
+            resolution: Some(R::R720p),
+            source: Some(S::WebRip),
+            group: Some("NTb".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.search_terms(),
+            r#""720p"|"1280x720" "web rip"|"webrip" "NTb""#
+        );
+
+        let filter = ReleaseFilter {
+            season: Some(2),
+            episode: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.search_terms(),
+            r#"s02*|s2e*|"s2"|"season 2"|2x*|02x*"#
+        );
+    }
+}
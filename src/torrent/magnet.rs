@@ -0,0 +1,105 @@
+use std::fmt::Display;
+
+use anyhow::bail;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Everything but RFC 3986 unreserved characters gets percent-encoded
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+#[doc = r"A BitTorrent magnet URI, as described at: https://www.bittorrent.org/beps/bep_0009.html
+
+magnet:?xt=urn:btih:<info-hash>&dn=<name>&tr=<tracker-url>&tr=<tracker-url>"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagnetLink {
+    info_hash: String,
+    display_name: Option<String>,
+    trackers: Vec<String>,
+}
+
+impl MagnetLink {
+    /// Accepts a 40 character hex or 32 character base32 infohash
+    pub fn new(info_hash: &str) -> anyhow::Result<Self> {
+        let info_hash = info_hash.trim();
+        let valid = match info_hash.len() {
+            40 => info_hash.chars().all(|c| c.is_ascii_hexdigit()),
+            32 => info_hash
+                .chars()
+                .all(|c| matches!(c.to_ascii_uppercase(), 'A'..='Z' | '2'..='7')),
+            _ => false,
+        };
+        if !valid {
+            bail!("Invalid infohash: '{}'", info_hash);
+        }
+
+        Ok(Self {
+            info_hash: info_hash.to_string(),
+            display_name: None,
+            trackers: Vec::new(),
+        })
+    }
+
+    pub fn display_name(mut self, name: impl Into<String>) -> Self {
+        self.display_name = Some(name.into());
+        self
+    }
+
+    pub fn trackers<I, S>(mut self, trackers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.trackers.extend(trackers.into_iter().map(Into::into));
+        self
+    }
+}
+
+impl Display for MagnetLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "magnet:?xt=urn:btih:{}", self.info_hash)?;
+        if let Some(name) = &self.display_name {
+            write!(f, "&dn={}", utf8_percent_encode(name, COMPONENT))?;
+        }
+        for tracker in &self.trackers {
+            write!(f, "&tr={}", utf8_percent_encode(tracker, COMPONENT))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MagnetLink;
+
+    const HASH: &str = "c9e15763f722f23e98a29decdfae341b98d53056";
+
+    #[test]
+    fn test_magnet_link() {
+        let magnet = MagnetLink::new(HASH)
+            .unwrap()
+            .display_name("Show Name S01E01 [1080p] & more")
+            .trackers(["udp://tracker.opentrackr.org:1337/announce"]);
+
+        assert_eq!(
+            magnet.to_string(),
+            "magnet:?xt=urn:btih:c9e15763f722f23e98a29decdfae341b98d53056\
+             &dn=Show%20Name%20S01E01%20%5B1080p%5D%20%26%20more\
+             &tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337%2Fannounce"
+        );
+    }
+
+    #[test]
+    fn test_infohash_validation() {
+        assert!(MagnetLink::new(HASH).is_ok());
+        assert!(MagnetLink::new(&HASH.to_uppercase()).is_ok());
+        assert!(MagnetLink::new("ZHQVOY7XELZD5GFCTXWN7LRUDOMNKMCW").is_ok());
+
+        assert!(MagnetLink::new("").is_err());
+        assert!(MagnetLink::new(&HASH[1..]).is_err());
+        assert!(MagnetLink::new(&HASH.replace('c', "x")).is_err());
+        assert!(MagnetLink::new("ZHQVOY7XELZD5GFCTXWN7LRUDOMNKMC1").is_err());
+    }
+}
@@ -1 +1,6 @@
+pub mod magnet;
+pub mod swarm;
+// NOTE: allow(dead_code) rationale: the client implements the whole of BEP 15,
+// but only scraping is used so far
+#[allow(dead_code)]
 pub mod udp;
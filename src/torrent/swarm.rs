@@ -0,0 +1,253 @@
+//! Keeps `swarm_stats` filled by scraping trackers in the background
+
+use std::time::Duration;
+
+use anyhow::bail;
+use chrono::Utc;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::config::SwarmConfig;
+use crate::database::rarbg::swarm::{RefreshOrder, SwarmCounts};
+use crate::database::rarbg::Database;
+
+use super::udp::announce::InfoHash;
+use super::udp::client::UdpTrackerClient;
+
+/// A dead tracker shouldn't hold up every round for the hour BEP 15's
+/// schedule would take to give up.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+const SCRAPE_RETRANSMISSIONS: u32 = 2;
+
+pub struct SwarmRefresher {
+    db: Database,
+    clients: Vec<UdpTrackerClient>,
+    order: RefreshOrder,
+    batch_size: u32,
+    max_age: Duration,
+    idle_interval: Duration,
+}
+
+impl SwarmRefresher {
+    pub async fn new(db: Database, config: &SwarmConfig) -> anyhow::Result<Self> {
+        let mut clients = Vec::with_capacity(config.trackers.len());
+        for tracker in &config.trackers {
+            match UdpTrackerClient::new(tracker).await {
+                Ok(client) => {
+                    clients.push(client.with_timeout(SCRAPE_TIMEOUT, SCRAPE_RETRANSMISSIONS))
+                }
+                Err(e) => warn!("Not scraping tracker {}: {}", tracker, e),
+            }
+        }
+        if clients.is_empty() {
+            bail!("none of the swarm.trackers could be resolved");
+        }
+
+        Ok(Self {
+            db,
+            clients,
+            order: config.order,
+            batch_size: config.batch_size,
+            max_age: Duration::from_secs(config.max_age),
+            idle_interval: Duration::from_secs(config.idle_interval),
+        })
+    }
+
+    /// Refreshes batch after batch for as long as the server runs
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                match self.refresh().await {
+                    Ok(0) => tokio::time::sleep(self.idle_interval).await,
+                    Ok(n) => debug!("Refreshed swarm stats of {} items", n),
+                    Err(e) => {
+                        warn!("Swarm stats refresh failed: {}", e);
+                        tokio::time::sleep(self.idle_interval).await
+                    }
+                }
+            }
+        })
+    }
+
+    /// Scrapes one batch of stale items and returns how many were stored
+    pub async fn refresh(&self) -> anyhow::Result<usize> {
+        let now = Utc::now().timestamp();
+        let scraped_before = now - self.max_age.as_secs() as i64;
+        let items = self
+            .db
+            .stale_items(self.order, scraped_before, self.batch_size)
+            .await?;
+        if items.is_empty() {
+            return Ok(0);
+        }
+
+        let hashes = items
+            .iter()
+            .map(|item| {
+                InfoHash::from_hex(&item.hash)
+                    .map_err(|e| debug!("Not scraping item {}: {}", item.id, e))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        let valid = hashes.iter().flatten().copied().collect::<Vec<_>>();
+
+        let mut best: Vec<Option<SwarmCounts>> = vec![None; valid.len()];
+        let mut answered = valid.is_empty();
+        for client in &self.clients {
+            let stats = match client.scrape(&valid).await {
+                Ok(stats) => stats,
+                Err(e) => {
+                    warn!("Could not scrape {}: {:#}", client.tracker(), e);
+                    continue;
+                }
+            };
+            answered = true;
+            for (best, stats) in best.iter_mut().zip(stats) {
+                let (seeders, leechers) = (stats.seeders as i64, stats.leechers as i64);
+                *best = Some(match *best {
+                    Some(counts) => SwarmCounts {
+                        seeders: counts.seeders.max(seeders),
+                        leechers: counts.leechers.max(leechers),
+                    },
+                    None => SwarmCounts { seeders, leechers },
+                });
+            }
+        }
+        if !answered {
+            bail!("none of the {} trackers answered", self.clients.len());
+        }
+
+        let mut best = best.into_iter();
+        let stats = items
+            .iter()
+            .zip(&hashes)
+            .map(|(item, hash)| (item.id, hash.and_then(|_| best.next().flatten())))
+            .collect::<Vec<_>>();
+        self.db.store_swarm_stats(&stats, now).await?;
+
+        Ok(items.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SwarmRefresher;
+    use crate::database::rarbg::swarm::RefreshOrder;
+    use crate::database::rarbg::{test_db, Database, RarbgTorrent};
+    use crate::database::{Database as _, Pagination, SearchRequest, Sort};
+    use crate::torrent::udp::client::UdpTrackerClient;
+    use crate::torrent::udp::stub::StubTracker;
+
+    async fn database() -> Database {
+        test_db(
+            r#"
+            INSERT INTO items (id, hash, title, dt, cat) VALUES
+                (1, '0505050505050505050505050505050505050505', 'Five', '', 'movies'),
+                (2, '0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a', 'Ten', '', 'movies'),
+                (3, 'not a hash', 'Broken', '', 'movies');
+            "#,
+        )
+        .await
+    }
+
+    async fn refresher(db: Database, trackers: &[&StubTracker]) -> SwarmRefresher {
+        let mut clients = Vec::new();
+        for tracker in trackers {
+            let client = UdpTrackerClient::from_addr(tracker.addr).await.unwrap();
+            clients.push(client.with_timeout(Duration::from_millis(50), 1));
+        }
+        SwarmRefresher {
+            db,
+            clients,
+            order: RefreshOrder::Newest,
+            batch_size: 10,
+            max_age: Duration::from_secs(60),
+            idle_interval: Duration::from_secs(60),
+        }
+    }
+
+    async fn search(db: &Database, sort: Sort, min_seeders: Option<u32>) -> Vec<RarbgTorrent> {
+        let request = SearchRequest {
+            sort,
+            min_seeders,
+            ..Default::default()
+        };
+        db.search(&request)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|item| RarbgTorrent::from_item(item, &[]).ok())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn stores_swarm_counts() {
+        let db = database().await;
+        assert!(search(&db, Sort::Last, None)
+            .await
+            .iter()
+            .all(|t| t.seeders.is_none()));
+
+        let tracker = StubTracker::spawn().await;
+        let refresher = refresher(db.clone(), &[&tracker]).await;
+        assert_eq!(refresher.refresh().await.unwrap(), 3);
+        // Everything is fresh now, including the item that can't be scraped
+        assert_eq!(refresher.refresh().await.unwrap(), 0);
+
+        let by_seeders = search(&db, Sort::Seeders, None).await;
+        let counts = by_seeders
+            .iter()
+            .map(|t| (t.title.as_str(), t.seeders, t.leechers))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            [("Ten", Some(10), Some(30)), ("Five", Some(5), Some(15))]
+        );
+
+        // Unscraped items come last, and cursors page through them too
+        let mut request = SearchRequest {
+            sort: Sort::Seeders,
+            pagination: Pagination {
+                amount: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut titles = Vec::new();
+        loop {
+            let page = db.search(&request).await.unwrap();
+            let next = db.next_cursor(&request, &page);
+            titles.extend(
+                page.into_iter()
+                    .map(|item| RarbgTorrent::from_item(item, &[]).ok().map(|t| t.title)),
+            );
+            request.pagination.after = next;
+            if next.is_none() {
+                break;
+            }
+        }
+        assert_eq!(
+            titles,
+            [Some("Ten".to_string()), Some("Five".to_string()), None]
+        );
+
+        let popular = search(&db, Sort::Last, Some(6)).await;
+        assert_eq!(popular.len(), 1);
+        assert_eq!(popular[0].title, "Ten");
+    }
+
+    #[tokio::test]
+    async fn survives_dead_trackers() {
+        let db = database().await;
+        let alive = StubTracker::spawn().await;
+        let dead = StubTracker::spawn().await.drop_next(usize::MAX);
+        let partial = refresher(db.clone(), &[&dead, &alive]).await;
+        assert_eq!(partial.refresh().await.unwrap(), 3);
+        assert_eq!(search(&db, Sort::Seeders, None).await[0].seeders, Some(10));
+
+        let unreachable = refresher(database().await, &[&dead]).await;
+        assert!(unreachable.refresh().await.is_err());
+    }
+}
@@ -1,65 +1,55 @@
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 
-use endian_trait::Endian;
+use bytes::{Buf, BufMut};
 
+use super::codec::{check_capacity, check_len, expect_action, CodecError, Decode, Encode};
 use super::{Action, Event};
 
-macro_rules! endian_func {
-    ($value:tt, $fn:ident) => {
-        fn $fn(mut self) -> Self {
-            self.$value = self
-                .$value
-                .iter()
-                .map(|byte| byte.$fn())
-                .collect::<Vec<_>>()
-                .try_into()
-                .unwrap();
-            self
-        }
-    };
-}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct InfoHash(pub [u8; 20]);
 
-macro_rules! impl_endian_for_byte_arr {
-    ($type:ident, $bytes:tt) => {
-        impl Endian for $type {
-            endian_func!($bytes, to_be);
-            endian_func!($bytes, to_le);
-            endian_func!($bytes, from_be);
-            endian_func!($bytes, from_le);
-        }
-    };
+impl InfoHash {
+    pub fn from_hex(hash: &str) -> anyhow::Result<Self> {
+        let mut bytes = [0; 20];
+        hex::decode_to_slice(hash.trim(), &mut bytes)
+            .map_err(|e| anyhow::anyhow!("Invalid infohash '{}': {}", hash, e))?;
+        Ok(Self(bytes))
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct InfoHash([u8; 20]);
-impl_endian_for_byte_arr!(InfoHash, 0);
+pub struct PeerId(pub [u8; 20]);
 
-#[derive(Debug, PartialEq, Eq)]
-#[repr(transparent)]
-struct PeerId([u8; 20]);
-impl_endian_for_byte_arr!(PeerId, 0);
+impl PeerId {
+    /// Azureus-style peer id: `-AB0100-` followed by random bytes
+    pub fn generate() -> Self {
+        let mut id = [0; 20];
+        id[..8].copy_from_slice(b"-AB0100-");
+        id[8..].copy_from_slice(&rand::random::<[u8; 12]>());
+        Self(id)
+    }
+}
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 struct IpAddr([u8; 4]);
-impl_endian_for_byte_arr!(IpAddr, 0);
 
 impl From<IpAddr> for Ipv4Addr {
     fn from(value: IpAddr) -> Self {
-        Ipv4Addr::from(value.from_be().0)
+        Ipv4Addr::from(value.0)
     }
 }
 
 impl From<Ipv4Addr> for IpAddr {
     fn from(value: Ipv4Addr) -> Self {
-        Self(value.octets()).to_be()
+        Self(value.octets())
     }
 }
 
-#[derive(Debug, Endian, PartialEq, Eq)]
-#[repr(C)]
-struct AnnounceInput {
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct AnnounceInput {
     connection_id: u64,
     action: Action,
     transaction_id: u32,
@@ -75,7 +65,449 @@ struct AnnounceInput {
     port: u16,
 }
 
-struct AnnounceOutput {
+/// What to announce, the connection and transaction ids are filled in by the
+/// client.
+#[derive(Debug, Clone)]
+pub struct AnnounceRequest {
+    pub info_hash: InfoHash,
+    pub peer_id: PeerId,
+    pub downloaded: u64,
+    pub left: u64,
+    pub uploaded: u64,
+    pub event: Event,
+    pub key: u32,
+    pub num_want: i32,
+    pub port: u16,
+}
+
+impl AnnounceRequest {
+    /// A "stopped" announce, which asks about the swarm without joining it:
+    /// trackers don't count a stopped peer, nor hand it out to others. Scrapes
+    /// are the cheaper way to the counts, see `UdpTrackerClient::scrape`.
+    pub fn new(info_hash: InfoHash) -> Self {
+        Self {
+            info_hash,
+            peer_id: PeerId::generate(),
+            downloaded: 0,
+            left: 0,
+            uploaded: 0,
+            event: Event::Stopped,
+            key: rand::random(),
+            num_want: 0,
+            port: 0,
+        }
+    }
+}
+
+impl AnnounceInput {
+    pub(super) fn new(connection_id: u64, transaction_id: u32, request: &AnnounceRequest) -> Self {
+        Self {
+            connection_id,
+            action: Action::Announce,
+            transaction_id,
+            info_hash: request.info_hash,
+            peer_id: request.peer_id,
+            downloaded: request.downloaded,
+            left: request.left,
+            uploaded: request.uploaded,
+            event: request.event,
+            ip: Ipv4Addr::UNSPECIFIED.into(),
+            key: request.key,
+            num_want: request.num_want,
+            port: request.port,
+        }
+    }
+}
+
+#[doc = r"spec described at at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+
+               announce input
+Offset  Size    Type    Name            Value
+0       64-bit  integer connection_id
+8       32-bit  integer action          1
+12      32-bit  integer transaction_id
+16      20-byte string  info_hash
+36      20-byte string  peer_id
+56      64-bit  integer downloaded
+64      64-bit  integer left
+72      64-bit  integer uploaded
+80      32-bit  integer event
+84      32-bit  integer ip              0
+88      32-bit  integer key
+92      32-bit  integer num_want        -1
+96      16-bit  integer port
+98"]
+pub(super) type AnnounceInputPacket = [u8; 98];
+
+impl Encode for AnnounceInput {
+    fn encoded_len(&self) -> usize {
+        98
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, CodecError> {
+        check_capacity(buf, 98)?;
+        let mut buf = &mut buf[..];
+        buf.put_u64(self.connection_id);
+        buf.put_u32(self.action as u32);
+        buf.put_u32(self.transaction_id);
+        buf.put_slice(&self.info_hash.0);
+        buf.put_slice(&self.peer_id.0);
+        buf.put_u64(self.downloaded);
+        buf.put_u64(self.left);
+        buf.put_u64(self.uploaded);
+        buf.put_u32(self.event as u32);
+        buf.put_slice(&self.ip.0);
+        buf.put_u32(self.key);
+        buf.put_i32(self.num_want);
+        buf.put_u16(self.port);
+        Ok(98)
+    }
+}
+
+impl Decode for AnnounceInput {
+    fn decode(mut buf: &[u8]) -> Result<Self, CodecError> {
+        check_len(buf, "announce input", |len| len == 98)?;
+        let connection_id = buf.get_u64();
+        expect_action(buf.get_u32(), Action::Announce)?;
+        let transaction_id = buf.get_u32();
+        let mut info_hash = InfoHash([0; 20]);
+        buf.copy_to_slice(&mut info_hash.0);
+        let mut peer_id = PeerId([0; 20]);
+        buf.copy_to_slice(&mut peer_id.0);
+        let downloaded = buf.get_u64();
+        let left = buf.get_u64();
+        let uploaded = buf.get_u64();
+        let event = buf.get_u32().try_into()?;
+        let mut ip = IpAddr([0; 4]);
+        buf.copy_to_slice(&mut ip.0);
+
+        Ok(Self {
+            connection_id,
+            action: Action::Announce,
+            transaction_id,
+            info_hash,
+            peer_id,
+            downloaded,
+            left,
+            uploaded,
+            event,
+            ip,
+            key: buf.get_u32(),
+            num_want: buf.get_i32(),
+            port: buf.get_u16(),
+        })
+    }
+}
+
+#[doc = r"spec described at at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+
+               announce output
+Offset  Size    Type    Name            Value
+0       32-bit  integer action          1
+4       32-bit  integer transaction_id
+8       32-bit  integer interval
+12      32-bit  integer leechers
+16      32-bit  integer seeders
+20 + 6 * n  32-bit  integer ip
+24 + 6 * n  16-bit  integer port
+20 + 6 * N
+
+Trackers answering over IPv6 send 18 byte peers instead, a 16 byte address
+followed by the port, as described in https://www.bittorrent.org/beps/bep_0015.html
+`Decode` reads IPv4 peers, see `decode_ipv6` for the other kind."]
+#[derive(Debug, PartialEq, Eq)]
+pub struct AnnounceOutput {
     action: Action,
     transaction_id: u32,
+    /// Seconds to wait before announcing again
+    pub interval: u32,
+    pub leechers: u32,
+    pub seeders: u32,
+    pub peers: Vec<SocketAddr>,
+}
+
+impl AnnounceOutput {
+    /// Decodes a response to an announce sent over IPv6
+    pub(super) fn decode_ipv6(buf: &[u8]) -> Result<Self, CodecError> {
+        Self::decode_peers(buf, 18)
+    }
+
+    fn decode_peers(mut buf: &[u8], peer_size: usize) -> Result<Self, CodecError> {
+        check_len(buf, "announce output", |len| {
+            len >= 20 && (len - 20).is_multiple_of(peer_size)
+        })?;
+        expect_action(buf.get_u32(), Action::Announce)?;
+        let transaction_id = buf.get_u32();
+        let interval = buf.get_u32();
+        let leechers = buf.get_u32();
+        let seeders = buf.get_u32();
+
+        let mut peers = Vec::with_capacity(buf.len() / peer_size);
+        while buf.has_remaining() {
+            let ip: std::net::IpAddr = if peer_size == 18 {
+                let mut ip = [0; 16];
+                buf.copy_to_slice(&mut ip);
+                Ipv6Addr::from(ip).into()
+            } else {
+                let mut ip = IpAddr([0; 4]);
+                buf.copy_to_slice(&mut ip.0);
+                Ipv4Addr::from(ip).into()
+            };
+            peers.push(SocketAddr::new(ip, buf.get_u16()));
+        }
+
+        Ok(Self {
+            action: Action::Announce,
+            transaction_id,
+            interval,
+            leechers,
+            seeders,
+            peers,
+        })
+    }
+
+    fn peer_size(&self) -> Result<usize, CodecError> {
+        if self.peers.iter().all(SocketAddr::is_ipv4) {
+            Ok(6)
+        } else if self.peers.iter().all(SocketAddr::is_ipv6) {
+            Ok(18)
+        } else {
+            Err(CodecError::MixedPeerFamilies)
+        }
+    }
+}
+
+impl Encode for AnnounceOutput {
+    fn encoded_len(&self) -> usize {
+        20 + self.peer_size().unwrap_or(18) * self.peers.len()
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, CodecError> {
+        let len = 20 + self.peer_size()? * self.peers.len();
+        check_capacity(buf, len)?;
+        let mut buf = &mut buf[..];
+        buf.put_u32(self.action as u32);
+        buf.put_u32(self.transaction_id);
+        buf.put_u32(self.interval);
+        buf.put_u32(self.leechers);
+        buf.put_u32(self.seeders);
+        for peer in &self.peers {
+            match peer.ip() {
+                std::net::IpAddr::V4(ip) => buf.put_slice(&IpAddr::from(ip).0),
+                std::net::IpAddr::V6(ip) => buf.put_slice(&ip.octets()),
+            }
+            buf.put_u16(peer.port());
+        }
+        Ok(len)
+    }
+}
+
+impl Decode for AnnounceOutput {
+    fn decode(buf: &[u8]) -> Result<Self, CodecError> {
+        Self::decode_peers(buf, 6)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    use byteorder::{BigEndian, ByteOrder};
+    use proptest::prelude::*;
+
+    use super::{
+        AnnounceInput, AnnounceInputPacket, AnnounceOutput, AnnounceRequest, InfoHash, PeerId,
+    };
+    use crate::torrent::udp::codec::tests::{assert_rejects_truncation, assert_round_trip};
+    use crate::torrent::udp::codec::{CodecError, Decode, Encode};
+    use crate::torrent::udp::{Action, Event};
+
+    #[test]
+    fn test_announce_input() {
+        let request = AnnounceRequest::new(InfoHash([9; 20]));
+        let announce_input = AnnounceInput::new(0x1234, 72, &request);
+
+        // Following spec at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+        let mut expected: AnnounceInputPacket = [0; 98];
+        BigEndian::write_u64(&mut expected[0..8], 0x1234);
+        BigEndian::write_u32(&mut expected[8..12], Action::Announce as u32);
+        BigEndian::write_u32(&mut expected[12..16], 72);
+        expected[16..36].copy_from_slice(&[9; 20]);
+        expected[36..56].copy_from_slice(&request.peer_id.0);
+        BigEndian::write_u32(&mut expected[80..84], Event::Stopped as u32);
+        BigEndian::write_u32(&mut expected[88..92], request.key);
+
+        let mut actual: AnnounceInputPacket = [0; 98];
+        assert_eq!(announce_input.encode(&mut actual), Ok(98));
+
+        assert_eq!(expected, actual);
+    }
+
+    fn announce_output_header(transaction_id: u32) -> Vec<u8> {
+        let mut buf = vec![0; 20];
+        BigEndian::write_u32(&mut buf[0..4], Action::Announce as u32);
+        BigEndian::write_u32(&mut buf[4..8], transaction_id);
+        BigEndian::write_u32(&mut buf[8..12], 1800);
+        BigEndian::write_u32(&mut buf[12..16], 3);
+        BigEndian::write_u32(&mut buf[16..20], 12);
+        buf
+    }
+
+    #[test]
+    fn test_announce_output_ipv4() {
+        let mut buf = announce_output_header(42);
+        buf.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+        buf.extend_from_slice(&[192, 168, 1, 20, 0, 80]);
+
+        let expected = AnnounceOutput {
+            action: Action::Announce,
+            transaction_id: 42,
+            interval: 1800,
+            leechers: 3,
+            seeders: 12,
+            peers: vec![
+                "10.0.0.1:6881".parse::<SocketAddr>().unwrap(),
+                "192.168.1.20:80".parse().unwrap(),
+            ],
+        };
+
+        assert_eq!(expected, AnnounceOutput::decode(&buf).unwrap());
+        assert!(AnnounceOutput::decode(&buf[..25]).is_err());
+        assert!(AnnounceOutput::decode(&buf[..19]).is_err());
+    }
+
+    #[test]
+    fn test_announce_output_ipv6() {
+        let mut buf = announce_output_header(42);
+        let ip: std::net::Ipv6Addr = "2001:db8::1".parse().unwrap();
+        buf.extend_from_slice(&ip.octets());
+        buf.extend_from_slice(&6881u16.to_be_bytes());
+
+        let expected = AnnounceOutput {
+            action: Action::Announce,
+            transaction_id: 42,
+            interval: 1800,
+            leechers: 3,
+            seeders: 12,
+            peers: vec!["[2001:db8::1]:6881".parse().unwrap()],
+        };
+
+        assert_eq!(expected, AnnounceOutput::decode_ipv6(&buf).unwrap());
+        assert!(AnnounceOutput::decode_ipv6(&buf[..26]).is_err());
+    }
+
+    #[test]
+    fn test_announce_output_mixed_peers() {
+        let output = AnnounceOutput {
+            action: Action::Announce,
+            transaction_id: 42,
+            interval: 1800,
+            leechers: 0,
+            seeders: 0,
+            peers: vec![
+                "10.0.0.1:6881".parse().unwrap(),
+                "[2001:db8::1]:6881".parse().unwrap(),
+            ],
+        };
+
+        assert_eq!(
+            output.encode(&mut [0; 64]),
+            Err(CodecError::MixedPeerFamilies)
+        );
+    }
+
+    fn event() -> impl Strategy<Value = Event> {
+        prop_oneof![
+            Just(Event::None),
+            Just(Event::Completed),
+            Just(Event::Started),
+            Just(Event::Stopped),
+        ]
+    }
+
+    prop_compose! {
+        fn announce_input()(
+            connection_id in any::<u64>(),
+            transaction_id in any::<u32>(),
+            info_hash in any::<[u8; 20]>(),
+            peer_id in any::<[u8; 20]>(),
+            downloaded in any::<u64>(),
+            left in any::<u64>(),
+            uploaded in any::<u64>(),
+            event in event(),
+            key in any::<u32>(),
+            num_want in any::<i32>(),
+            port in any::<u16>(),
+        ) -> AnnounceInput {
+            let request = AnnounceRequest {
+                info_hash: InfoHash(info_hash),
+                peer_id: PeerId(peer_id),
+                downloaded,
+                left,
+                uploaded,
+                event,
+                key,
+                num_want,
+                port,
+            };
+            AnnounceInput::new(connection_id, transaction_id, &request)
+        }
+    }
+
+    fn announce_output<P: Strategy<Value = SocketAddr>>(
+        peer: P,
+    ) -> impl Strategy<Value = AnnounceOutput> {
+        (
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+            any::<u32>(),
+            proptest::collection::vec(peer, 0..50),
+        )
+            .prop_map(|(transaction_id, interval, leechers, seeders, peers)| {
+                AnnounceOutput {
+                    action: Action::Announce,
+                    transaction_id,
+                    interval,
+                    leechers,
+                    seeders,
+                    peers,
+                }
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn announce_input_round_trip(message in announce_input()) {
+            assert_round_trip(&message);
+            assert_rejects_truncation(&message, 98);
+        }
+
+        #[test]
+        fn announce_output_ipv4_round_trip(
+            message in announce_output(
+                any::<(u32, u16)>().prop_map(|(ip, port)| (Ipv4Addr::from(ip), port).into())
+            )
+        ) {
+            assert_round_trip(&message);
+            assert_rejects_truncation(&message, 20);
+        }
+
+        #[test]
+        fn announce_output_ipv6_round_trip(
+            message in announce_output(
+                any::<(u128, u16)>().prop_map(|(ip, port)| (Ipv6Addr::from(ip), port).into())
+            )
+        ) {
+            let mut buf = vec![0; message.encoded_len()];
+            message.encode(&mut buf).unwrap();
+            prop_assert_eq!(AnnounceOutput::decode_ipv6(&buf), Ok(message));
+        }
+
+        #[test]
+        fn announce_output_never_panics(buf in proptest::collection::vec(any::<u8>(), 0..128)) {
+            let _ = AnnounceOutput::decode(&buf);
+            let _ = AnnounceOutput::decode_ipv6(&buf);
+        }
+    }
 }
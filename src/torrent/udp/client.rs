@@ -0,0 +1,422 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use byteorder::{BigEndian, ByteOrder};
+use bytes::BytesMut;
+use reqwest::Url;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::Mutex;
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, trace, warn};
+
+use super::announce::{AnnounceInput, AnnounceOutput, AnnounceRequest, InfoHash};
+use super::codec::{CodecError, Decode, Encode};
+use super::connect::{ConnectInput, ConnectOutput};
+use super::error::{ErrorOutput, TrackerError};
+use super::scrape::{ScrapeInput, ScrapeOutput, ScrapeStats, MAX_SCRAPE_HASHES};
+use super::Action;
+
+/// A connection id may be used for one minute after it was received
+const CONNECTION_ID_TTL: Duration = Duration::from_secs(60);
+/// BEP 15: wait 15 * 2 ^ n seconds before retransmitting
+const TIMEOUT_BASE: Duration = Duration::from_secs(15);
+/// BEP 15: give up after n reaches 8
+const MAX_RETRANSMISSIONS: u32 = 8;
+const MAX_PACKET_SIZE: usize = 65_536;
+
+#[derive(Debug)]
+struct Connection {
+    id: u64,
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+struct Inner {
+    socket: UdpSocket,
+    connection: Option<Connection>,
+    send_buf: BytesMut,
+    recv_buf: Box<[u8]>,
+}
+
+#[doc = r"UDP tracker client, following https://www.bittorrent.org/beps/bep_0015.html
+
+Requests are sent one at a time over a single socket, so responses can be
+matched to requests by their transaction_id."]
+#[derive(Debug)]
+pub struct UdpTrackerClient {
+    tracker: SocketAddr,
+    inner: Mutex<Inner>,
+    timeout_base: Duration,
+    max_retransmissions: u32,
+}
+
+impl UdpTrackerClient {
+    /// Accepts either `host:port` or a `udp://host:port/announce` URL
+    pub async fn new(tracker: &str) -> anyhow::Result<Self> {
+        let host = match Url::parse(tracker) {
+            Ok(url) if url.scheme() == "udp" => {
+                let host = url.host_str().context("tracker URL without host")?;
+                let port = url.port().context("tracker URL without port")?;
+                format!("{host}:{port}")
+            }
+            Ok(url) => bail!("Not an UDP tracker: {}", url),
+            Err(_) => tracker.to_string(),
+        };
+        let addr = lookup_host(&host)
+            .await?
+            .next()
+            .with_context(|| format!("could not resolve tracker {}", host))?;
+
+        Self::from_addr(addr).await
+    }
+
+    pub async fn from_addr(tracker: SocketAddr) -> anyhow::Result<Self> {
+        let bind: SocketAddr = if tracker.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(tracker).await?;
+
+        Ok(Self {
+            tracker,
+            inner: Mutex::new(Inner {
+                socket,
+                connection: None,
+                send_buf: BytesMut::with_capacity(MAX_PACKET_SIZE),
+                recv_buf: vec![0; MAX_PACKET_SIZE].into_boxed_slice(),
+            }),
+            timeout_base: TIMEOUT_BASE,
+            max_retransmissions: MAX_RETRANSMISSIONS,
+        })
+    }
+
+    /// Overrides the BEP 15 retransmission schedule of 15 * 2 ^ n seconds for
+    /// n in 0..=8.
+    pub fn with_timeout(mut self, base: Duration, max_retransmissions: u32) -> Self {
+        self.timeout_base = base;
+        self.max_retransmissions = max_retransmissions;
+        self
+    }
+
+    pub fn tracker(&self) -> SocketAddr {
+        self.tracker
+    }
+
+    pub async fn announce(&self, request: &AnnounceRequest) -> anyhow::Result<AnnounceOutput> {
+        let decode = if self.tracker.is_ipv6() {
+            AnnounceOutput::decode_ipv6
+        } else {
+            AnnounceOutput::decode
+        };
+        self.request(
+            Action::Announce,
+            |connection_id, transaction_id| {
+                Ok(AnnounceInput::new(connection_id, transaction_id, request))
+            },
+            decode,
+        )
+        .await
+    }
+
+    /// Scrapes any number of infohashes, in batches of `MAX_SCRAPE_HASHES`.
+    /// Stats come back in the same order as `info_hashes`.
+    pub async fn scrape(&self, info_hashes: &[InfoHash]) -> anyhow::Result<Vec<ScrapeStats>> {
+        let mut stats = Vec::with_capacity(info_hashes.len());
+        for batch in info_hashes.chunks(MAX_SCRAPE_HASHES) {
+            let output: ScrapeOutput = self
+                .request(
+                    Action::Scrape,
+                    |connection_id, transaction_id| {
+                        ScrapeInput::new(connection_id, transaction_id, batch)
+                    },
+                    ScrapeOutput::decode,
+                )
+                .await?;
+            if output.stats.len() != batch.len() {
+                bail!(
+                    "Tracker {} answered {} of {} scraped infohashes",
+                    self.tracker,
+                    output.stats.len(),
+                    batch.len()
+                );
+            }
+            stats.extend(output.stats);
+        }
+        Ok(stats)
+    }
+
+    /// Sends the message built by `build` from a valid connection_id and a
+    /// fresh transaction_id, retransmitting until the tracker answers.
+    async fn request<M, R, F>(
+        &self,
+        action: Action,
+        build: F,
+        decode: fn(&[u8]) -> Result<R, CodecError>,
+    ) -> anyhow::Result<R>
+    where
+        M: Encode,
+        F: Fn(u64, u32) -> Result<M, CodecError>,
+    {
+        let mut inner = self.inner.lock().await;
+
+        for n in 0..=self.max_retransmissions {
+            let timeout = self.timeout_base * 2u32.pow(n);
+
+            let connection_id = match inner.connection_id() {
+                Some(id) => id,
+                None => match self.connect(&mut inner, timeout).await? {
+                    Some(id) => id,
+                    None => continue,
+                },
+            };
+
+            let transaction_id = rand::random();
+            let message = build(connection_id, transaction_id)?;
+            if let Some(response) = inner.exchange(&message, transaction_id, timeout).await? {
+                self.check_response(response, action)?;
+                return decode(response).with_context(|| {
+                    format!(
+                        "Malformed {:?} response from tracker {}",
+                        action, self.tracker
+                    )
+                });
+            }
+            debug!("Tracker {} timed out after {:?}", self.tracker, timeout);
+        }
+
+        bail!(
+            "Tracker {} did not respond after {} retransmissions",
+            self.tracker,
+            self.max_retransmissions
+        )
+    }
+
+    async fn connect(&self, inner: &mut Inner, timeout: Duration) -> anyhow::Result<Option<u64>> {
+        let transaction_id = rand::random();
+        let message = ConnectInput::new(transaction_id);
+
+        let Some(response) = inner.exchange(&message, transaction_id, timeout).await? else {
+            debug!(
+                "Tracker {} connect timed out after {:?}",
+                self.tracker, timeout
+            );
+            return Ok(None);
+        };
+        self.check_response(response, Action::Connect)?;
+        let output = ConnectOutput::decode(response)
+            .with_context(|| format!("Malformed connect response from tracker {}", self.tracker))?;
+
+        trace!(
+            "Connected to {} with id {}",
+            self.tracker,
+            output.connection_id
+        );
+        inner.connection = Some(Connection {
+            id: output.connection_id,
+            expires_at: Instant::now() + CONNECTION_ID_TTL,
+        });
+        Ok(Some(output.connection_id))
+    }
+
+    /// Turns error packets into a `TrackerError`, and makes sure anything else
+    /// answers an `expected` request.
+    fn check_response(&self, response: &[u8], expected: Action) -> anyhow::Result<()> {
+        let action = response
+            .get(..4)
+            .map(BigEndian::read_u32)
+            .ok_or(CodecError::InvalidLength {
+                message: "response",
+                len: response.len(),
+            })
+            .and_then(Action::try_from)
+            .with_context(|| format!("Malformed response from tracker {}", self.tracker))?;
+        if action == Action::Error {
+            let error = TrackerError::from(ErrorOutput::decode(response)?);
+            warn!(
+                "Tracker {} answered with an error: {}",
+                self.tracker, error.message
+            );
+            return Err(anyhow::Error::new(error))
+                .with_context(|| format!("{:?} request to tracker {}", expected, self.tracker));
+        }
+        if action != expected {
+            bail!(
+                "Expected {:?} response from tracker {}, got {:?}",
+                expected,
+                self.tracker,
+                action
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Inner {
+    fn connection_id(&self) -> Option<u64> {
+        self.connection
+            .as_ref()
+            .filter(|c| c.expires_at > Instant::now())
+            .map(|c| c.id)
+    }
+
+    /// Sends `message` and waits up to `timeout` for the response carrying
+    /// `transaction_id`, dropping anything else that arrives meanwhile.
+    async fn exchange(
+        &mut self,
+        message: &impl Encode,
+        transaction_id: u32,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<&[u8]>> {
+        self.send_buf.clear();
+        message.encode_to(&mut self.send_buf)?;
+        self.socket.send(&self.send_buf).await?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let len = match timeout_at(deadline, self.socket.recv(&mut self.recv_buf)).await {
+                Ok(len) => len?,
+                Err(_) => return Ok(None),
+            };
+            if len >= 8 && BigEndian::read_u32(&self.recv_buf[4..8]) == transaction_id {
+                return Ok(Some(&self.recv_buf[..len]));
+            }
+            trace!("Dropping unexpected packet of {} bytes", len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::UdpTrackerClient;
+    use crate::torrent::udp::announce::{AnnounceRequest, InfoHash};
+    use crate::torrent::udp::error::TrackerError;
+    use crate::torrent::udp::scrape::{ScrapeStats, MAX_SCRAPE_HASHES};
+    use crate::torrent::udp::stub::StubTracker;
+
+    const HASH: InfoHash = InfoHash([7; 20]);
+
+    async fn client(tracker: &StubTracker) -> UdpTrackerClient {
+        UdpTrackerClient::from_addr(tracker.addr)
+            .await
+            .unwrap()
+            .with_timeout(Duration::from_millis(50), 3)
+    }
+
+    #[tokio::test]
+    async fn reuses_connection_id() {
+        let tracker = StubTracker::spawn().await;
+        let client = client(&tracker).await;
+
+        client.announce(&AnnounceRequest::new(HASH)).await.unwrap();
+        client.scrape(&[HASH]).await.unwrap();
+
+        assert_eq!(tracker.connects(), 1);
+    }
+
+    #[tokio::test]
+    async fn announces_swarm() {
+        let tracker = StubTracker::spawn().await;
+        let client = client(&tracker).await;
+
+        let output = client.announce(&AnnounceRequest::new(HASH)).await.unwrap();
+
+        assert_eq!(output.interval, 1800);
+        assert_eq!(output.seeders, 7);
+        assert_eq!(output.leechers, 21);
+        assert_eq!(output.peers, vec!["10.0.0.1:6881".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn scrapes_every_hash() {
+        let tracker = StubTracker::spawn().await;
+        let client = client(&tracker).await;
+
+        let stats = client.scrape(&[HASH, InfoHash([1; 20])]).await.unwrap();
+
+        assert_eq!(
+            stats,
+            vec![
+                ScrapeStats {
+                    seeders: 7,
+                    completed: 14,
+                    leechers: 21
+                },
+                ScrapeStats {
+                    seeders: 1,
+                    completed: 2,
+                    leechers: 3
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scrapes_in_batches() {
+        let tracker = StubTracker::spawn().await;
+        let client = client(&tracker).await;
+
+        let hashes: Vec<_> = (0..2 * MAX_SCRAPE_HASHES + 1)
+            .map(|i| InfoHash([i as u8; 20]))
+            .collect();
+        let stats = client.scrape(&hashes).await.unwrap();
+
+        assert_eq!(tracker.received(), 4);
+        assert_eq!(stats.len(), hashes.len());
+        assert!(stats
+            .iter()
+            .zip(&hashes)
+            .all(|(stats, hash)| stats.seeders == hash.0[0] as u32));
+    }
+
+    #[tokio::test]
+    async fn retransmits_lost_packets() {
+        let tracker = StubTracker::spawn().await.drop_next(2);
+        let client = client(&tracker).await;
+
+        client.announce(&AnnounceRequest::new(HASH)).await.unwrap();
+
+        assert_eq!(tracker.received(), 4);
+    }
+
+    #[tokio::test]
+    async fn ignores_foreign_transaction_ids() {
+        let tracker = StubTracker::spawn().await.send_stray_packets();
+        let client = client(&tracker).await;
+
+        client.scrape(&[HASH]).await.unwrap();
+
+        assert_eq!(tracker.received(), 2);
+    }
+
+    #[tokio::test]
+    async fn surfaces_tracker_errors() {
+        let tracker = StubTracker::spawn()
+            .await
+            .fail_with("torrent not registered");
+        let client = client(&tracker).await;
+
+        let err = client.scrape(&[HASH]).await.unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<TrackerError>(),
+            Some(&TrackerError {
+                message: "torrent not registered".to_string()
+            })
+        );
+        assert!(err.to_string().contains(&tracker.addr.to_string()));
+    }
+
+    #[tokio::test]
+    async fn gives_up_eventually() {
+        let tracker = StubTracker::spawn().await.drop_next(usize::MAX);
+        let client = client(&tracker).await;
+
+        assert!(client.scrape(&[HASH]).await.is_err());
+        assert_eq!(tracker.received(), 4);
+    }
+}
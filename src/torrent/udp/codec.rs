@@ -0,0 +1,158 @@
+use std::fmt::Display;
+
+use bytes::BytesMut;
+
+use super::Action;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    /// The buffer can't hold the whole message
+    BufferTooShort {
+        needed: usize,
+        available: usize,
+    },
+    /// The packet length doesn't match the message layout
+    InvalidLength {
+        message: &'static str,
+        len: usize,
+    },
+    InvalidAction(u32),
+    UnexpectedAction {
+        expected: Action,
+        actual: Action,
+    },
+    InvalidEvent(u32),
+    TooManyInfoHashes(usize),
+    /// IPv4 and IPv6 peers can't be mixed in one compact peer list
+    MixedPeerFamilies,
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::BufferTooShort { needed, available } => {
+                write!(f, "buffer too short: need {needed} bytes, have {available}")
+            }
+            CodecError::InvalidLength { message, len } => {
+                write!(f, "invalid {message} length: {len} bytes")
+            }
+            CodecError::InvalidAction(action) => write!(f, "invalid action: {action}"),
+            CodecError::UnexpectedAction { expected, actual } => {
+                write!(f, "expected {expected:?} packet, got {actual:?}")
+            }
+            CodecError::InvalidEvent(event) => write!(f, "invalid event: {event}"),
+            CodecError::TooManyInfoHashes(n) => write!(f, "too many infohashes: {n}"),
+            CodecError::MixedPeerFamilies => f.write_str("mixed IPv4 and IPv6 peers"),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// A message that can be written to the wire, big-endian as BEP 15 requires
+pub trait Encode {
+    /// Exact number of bytes `encode` writes
+    fn encoded_len(&self) -> usize;
+
+    /// Writes the message at the start of `buf`, which must hold at least
+    /// `encoded_len` bytes. Returns the number of bytes written.
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, CodecError>;
+
+    /// Appends the message to `buf`, growing it as needed
+    fn encode_to(&self, buf: &mut BytesMut) -> Result<usize, CodecError> {
+        let start = buf.len();
+        buf.resize(start + self.encoded_len(), 0);
+        let written = self.encode(&mut buf[start..]);
+        if written.is_err() {
+            buf.truncate(start);
+        }
+        written
+    }
+}
+
+/// A message that can be read from the wire
+pub trait Decode: Sized {
+    fn decode(buf: &[u8]) -> Result<Self, CodecError>;
+}
+
+/// Makes sure `buf` is big enough before anything gets written to it, so the
+/// `BufMut` calls that follow can't panic.
+pub(super) fn check_capacity(buf: &[u8], needed: usize) -> Result<(), CodecError> {
+    if buf.len() < needed {
+        return Err(CodecError::BufferTooShort {
+            needed,
+            available: buf.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Same as `check_capacity`, for packets being read
+pub(super) fn check_len(
+    buf: &[u8],
+    message: &'static str,
+    valid: impl FnOnce(usize) -> bool,
+) -> Result<(), CodecError> {
+    if !valid(buf.len()) {
+        return Err(CodecError::InvalidLength {
+            message,
+            len: buf.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Reads the action at the start of every tracker response, or at offset 8
+/// of every request, and checks it's the one the message type expects.
+pub(super) fn expect_action(raw: u32, expected: Action) -> Result<(), CodecError> {
+    let actual = Action::try_from(raw)?;
+    if actual != expected {
+        return Err(CodecError::UnexpectedAction { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::fmt::Debug;
+
+    use bytes::BytesMut;
+
+    use super::{CodecError, Decode, Encode};
+
+    /// Encodes `message` every way the trait allows, checks all of them agree
+    /// and that decoding gives back the same message.
+    pub fn assert_round_trip<M>(message: &M)
+    where
+        M: Encode + Decode + PartialEq + Debug,
+    {
+        let len = message.encoded_len();
+
+        let mut buf = vec![0; len + 3];
+        assert_eq!(message.encode(&mut buf).unwrap(), len);
+        assert_eq!(&M::decode(&buf[..len]).unwrap(), message);
+
+        let mut bytes = BytesMut::from(&b"xyz"[..]);
+        assert_eq!(message.encode_to(&mut bytes).unwrap(), len);
+        assert_eq!(&bytes[3..], &buf[..len]);
+
+        assert!(matches!(
+            message.encode(&mut buf[..len - 1]),
+            Err(CodecError::BufferTooShort { .. })
+        ));
+    }
+
+    /// Every prefix of the encoded message shorter than `min_len` must be
+    /// rejected. Variable length messages have valid shorter forms, hence the
+    /// explicit bound.
+    pub fn assert_rejects_truncation<M>(message: &M, min_len: usize)
+    where
+        M: Encode + Decode + Debug,
+    {
+        let mut buf = vec![0; message.encoded_len()];
+        message.encode(&mut buf).unwrap();
+        for len in 0..min_len {
+            assert!(M::decode(&buf[..len]).is_err(), "decoded {len} bytes");
+        }
+    }
+}
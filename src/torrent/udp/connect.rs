@@ -1,5 +1,6 @@
-use endian_trait::Endian;
+use bytes::{Buf, BufMut};
 
+use super::codec::{check_capacity, check_len, expect_action, CodecError, Decode, Encode};
 use super::{Action, CONNECTION_ID};
 
 #[doc = r"spec described at at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
@@ -10,18 +11,17 @@ Offset	Size    Type    Name                    Value
 8       32-bit  integer	action                      0
 12      32-bit  integer	transaction_id
 16"]
-#[derive(Debug, Endian, PartialEq, Eq)]
-#[repr(C)]
-struct ConnectInput {
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct ConnectInput {
     connection_id: u64,
     action: Action,
     pub(crate) transaction_id: u32,
 }
 
-type ConnectInputPacket = [u8; 16];
+pub(super) type ConnectInputPacket = [u8; 16];
 
 impl ConnectInput {
-    fn new(transaction_id: u32) -> Self {
+    pub(super) fn new(transaction_id: u32) -> Self {
         Self {
             connection_id: CONNECTION_ID,
             action: Action::Connect,
@@ -30,9 +30,32 @@ impl ConnectInput {
     }
 }
 
-impl From<ConnectInput> for ConnectInputPacket {
-    fn from(val: ConnectInput) -> Self {
-        unsafe { std::mem::transmute(val.to_be()) }
+impl Encode for ConnectInput {
+    fn encoded_len(&self) -> usize {
+        16
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, CodecError> {
+        check_capacity(buf, 16)?;
+        let mut buf = &mut buf[..];
+        buf.put_u64(self.connection_id);
+        buf.put_u32(self.action as u32);
+        buf.put_u32(self.transaction_id);
+        Ok(16)
+    }
+}
+
+impl Decode for ConnectInput {
+    fn decode(mut buf: &[u8]) -> Result<Self, CodecError> {
+        check_len(buf, "connect input", |len| len == 16)?;
+        let connection_id = buf.get_u64();
+        let action = buf.get_u32();
+        expect_action(action, Action::Connect)?;
+        Ok(Self {
+            connection_id,
+            action: Action::Connect,
+            transaction_id: buf.get_u32(),
+        })
     }
 }
 
@@ -44,30 +67,54 @@ Offset	Size    Type    Name                    Value
 4       32-bit  integer	transaction_id
 8       64-bit  integer connection_id
 16"]
-#[derive(Debug, Endian, PartialEq, Eq)]
-#[repr(C)]
-struct ConnectOutput {
-    action: Action,
-    transaction_id: u32,
-    connection_id: u64,
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct ConnectOutput {
+    pub(super) action: Action,
+    pub(super) transaction_id: u32,
+    pub(super) connection_id: u64,
 }
 
-type ConnectOutputPacket = [u8; 16];
+pub(super) type ConnectOutputPacket = [u8; 16];
 
-impl From<ConnectOutputPacket> for ConnectOutput {
-    fn from(value: ConnectOutputPacket) -> Self {
-        let v: ConnectOutput = unsafe { std::mem::transmute(value) };
-        v.from_be()
+impl Encode for ConnectOutput {
+    fn encoded_len(&self) -> usize {
+        16
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, CodecError> {
+        check_capacity(buf, 16)?;
+        let mut buf = &mut buf[..];
+        buf.put_u32(self.action as u32);
+        buf.put_u32(self.transaction_id);
+        buf.put_u64(self.connection_id);
+        Ok(16)
+    }
+}
+
+impl Decode for ConnectOutput {
+    fn decode(mut buf: &[u8]) -> Result<Self, CodecError> {
+        // Trackers may pad responses, only the first 16 bytes matter
+        check_len(buf, "connect output", |len| len >= 16)?;
+        expect_action(buf.get_u32(), Action::Connect)?;
+        Ok(Self {
+            action: Action::Connect,
+            transaction_id: buf.get_u32(),
+            connection_id: buf.get_u64(),
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{ConnectInput, ConnectOutput, CONNECTION_ID};
+    use crate::torrent::udp::codec::tests::{assert_rejects_truncation, assert_round_trip};
+    use crate::torrent::udp::codec::{CodecError, Decode, Encode};
     use crate::torrent::udp::connect::ConnectInputPacket;
+    use crate::torrent::udp::stub::{StubTracker, STUB_CONNECTION_ID};
     use crate::torrent::udp::{connect::ConnectOutputPacket, Action};
     use byteorder::{BigEndian, ByteOrder};
-    use std::mem::zeroed;
+    use proptest::prelude::*;
+    use tokio::net::UdpSocket;
 
     #[test]
     fn test_connect_input() {
@@ -77,12 +124,13 @@ mod tests {
         let connect_input = ConnectInput::new(transaction_id);
 
         // Following spec at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
-        let mut expected: ConnectInputPacket = unsafe { zeroed() };
+        let mut expected: ConnectInputPacket = [0; 16];
         BigEndian::write_u64(&mut expected[0..8], CONNECTION_ID);
         BigEndian::write_u32(&mut expected[8..12], action as u32);
         BigEndian::write_u32(&mut expected[12..16], transaction_id);
 
-        let actual: ConnectInputPacket = connect_input.into();
+        let mut actual: ConnectInputPacket = [0; 16];
+        assert_eq!(connect_input.encode(&mut actual), Ok(16));
 
         assert_eq!(expected, actual);
     }
@@ -93,7 +141,7 @@ mod tests {
         let transaction_id: u32 = 42;
 
         // Following spec at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
-        let mut buf: ConnectOutputPacket = unsafe { zeroed() };
+        let mut buf: ConnectOutputPacket = [0; 16];
         BigEndian::write_u32(&mut buf[0..4], action as u32);
         BigEndian::write_u32(&mut buf[4..8], transaction_id);
         BigEndian::write_u64(&mut buf[8..16], CONNECTION_ID);
@@ -104,10 +152,73 @@ mod tests {
             connection_id: CONNECTION_ID,
         };
 
-        assert_eq!(expected, buf.into());
+        assert_eq!(Ok(expected), ConnectOutput::decode(&buf));
     }
 
     #[test]
-    fn connect_to_tracker() {
+    fn test_connect_output_wrong_action() {
+        let mut buf: ConnectOutputPacket = [0; 16];
+        BigEndian::write_u32(&mut buf[0..4], Action::Announce as u32);
+
+        assert_eq!(
+            ConnectOutput::decode(&buf),
+            Err(CodecError::UnexpectedAction {
+                expected: Action::Connect,
+                actual: Action::Announce
+            })
+        );
+
+        BigEndian::write_u32(&mut buf[0..4], 1337);
+        assert_eq!(
+            ConnectOutput::decode(&buf),
+            Err(CodecError::InvalidAction(1337))
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn connect_input_round_trip(transaction_id in any::<u32>()) {
+            let message = ConnectInput::new(transaction_id);
+            assert_round_trip(&message);
+            assert_rejects_truncation(&message, 16);
+        }
+
+        #[test]
+        fn connect_output_round_trip(transaction_id in any::<u32>(), connection_id in any::<u64>()) {
+            let message = ConnectOutput {
+                action: Action::Connect,
+                transaction_id,
+                connection_id,
+            };
+            assert_round_trip(&message);
+            assert_rejects_truncation(&message, 16);
+        }
+
+        #[test]
+        fn connect_output_never_panics(buf in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let _ = ConnectOutput::decode(&buf);
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_to_tracker() {
+        let tracker = StubTracker::spawn().await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(tracker.addr).await.unwrap();
+
+        let mut packet: ConnectInputPacket = [0; 16];
+        ConnectInput::new(1337).encode(&mut packet).unwrap();
+        socket.send(&packet).await.unwrap();
+
+        let mut buf: ConnectOutputPacket = [0; 16];
+        assert_eq!(socket.recv(&mut buf).await.unwrap(), 16);
+
+        let expected = ConnectOutput {
+            action: Action::Connect,
+            transaction_id: 1337,
+            connection_id: STUB_CONNECTION_ID,
+        };
+
+        assert_eq!(Ok(expected), ConnectOutput::decode(&buf));
     }
 }
@@ -0,0 +1,118 @@
+use std::fmt::Display;
+
+use bytes::{Buf, BufMut};
+
+use super::codec::{check_capacity, check_len, expect_action, CodecError, Decode, Encode};
+use super::Action;
+
+#[doc = r"spec described at at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+
+               error output
+Offset  Size    Type    Name            Value
+0       32-bit  integer action          3
+4       32-bit  integer transaction_id
+8       string  message"]
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct ErrorOutput {
+    action: Action,
+    pub(super) transaction_id: u32,
+    pub(super) message: String,
+}
+
+impl Encode for ErrorOutput {
+    fn encoded_len(&self) -> usize {
+        8 + self.message.len()
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, CodecError> {
+        let len = self.encoded_len();
+        check_capacity(buf, len)?;
+        let mut buf = &mut buf[..];
+        buf.put_u32(self.action as u32);
+        buf.put_u32(self.transaction_id);
+        buf.put_slice(self.message.as_bytes());
+        Ok(len)
+    }
+}
+
+impl Decode for ErrorOutput {
+    fn decode(mut buf: &[u8]) -> Result<Self, CodecError> {
+        check_len(buf, "error output", |len| len >= 8)?;
+        expect_action(buf.get_u32(), Action::Error)?;
+        Ok(Self {
+            action: Action::Error,
+            transaction_id: buf.get_u32(),
+            message: String::from_utf8_lossy(buf)
+                .trim_end_matches('\0')
+                .to_string(),
+        })
+    }
+}
+
+/// The tracker answered a request with an error message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackerError {
+    pub message: String,
+}
+
+impl Display for TrackerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tracker error: {}", self.message)
+    }
+}
+
+impl std::error::Error for TrackerError {}
+
+impl From<ErrorOutput> for TrackerError {
+    fn from(value: ErrorOutput) -> Self {
+        Self {
+            message: value.message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, ByteOrder};
+    use proptest::prelude::*;
+
+    use super::ErrorOutput;
+    use crate::torrent::udp::codec::tests::{assert_rejects_truncation, assert_round_trip};
+    use crate::torrent::udp::codec::Decode;
+    use crate::torrent::udp::Action;
+
+    #[test]
+    fn test_error_output() {
+        let action = Action::Error;
+        let transaction_id: u32 = 42;
+        let message = "unregistered torrent";
+
+        // Following spec at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+        let mut buf = vec![0; 8];
+        BigEndian::write_u32(&mut buf[0..4], action as u32);
+        BigEndian::write_u32(&mut buf[4..8], transaction_id);
+        buf.extend_from_slice(message.as_bytes());
+
+        let expected = ErrorOutput {
+            action,
+            transaction_id,
+            message: message.to_string(),
+        };
+
+        assert_eq!(Ok(expected), ErrorOutput::decode(&buf));
+        assert!(ErrorOutput::decode(&buf[..7]).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn error_output_round_trip(transaction_id in any::<u32>(), message in "[^\0]{0,64}") {
+            let message = ErrorOutput {
+                action: Action::Error,
+                transaction_id,
+                message,
+            };
+            assert_round_trip(&message);
+            assert_rejects_truncation(&message, 8);
+        }
+    }
+}
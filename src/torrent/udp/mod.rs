@@ -1,26 +1,58 @@
-pub mod connect;
 pub mod announce;
+pub mod client;
+pub mod codec;
+pub mod connect;
+pub mod error;
+pub mod scrape;
+#[cfg(test)]
+pub(crate) mod stub;
 
-use std::net::Ipv4Addr;
-
-use endian_trait::Endian;
+use self::codec::CodecError;
 
 const CONNECTION_ID: u64 = 0x41727101980;
 
-#[derive(Debug, Endian, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
-enum Action {
+pub enum Action {
     Connect = 0,
     Announce = 1,
     Scrape = 2,
     Error = 3,
 }
 
-#[derive(Debug, Endian, Clone, Copy, PartialEq, Eq)]
+impl TryFrom<u32> for Action {
+    type Error = CodecError;
+
+    fn try_from(value: u32) -> Result<Self, CodecError> {
+        match value {
+            0 => Ok(Action::Connect),
+            1 => Ok(Action::Announce),
+            2 => Ok(Action::Scrape),
+            3 => Ok(Action::Error),
+            _ => Err(CodecError::InvalidAction(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
-enum Event {
+pub enum Event {
     None = 0,
     Completed = 1,
     Started = 2,
     Stopped = 3,
 }
+
+impl TryFrom<u32> for Event {
+    type Error = CodecError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Completed),
+            2 => Ok(Self::Started),
+            3 => Ok(Self::Stopped),
+            _ => Err(CodecError::InvalidEvent(value)),
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::borrow::Cow;
+
+use bytes::{Buf, BufMut};
+
+use super::announce::InfoHash;
+use super::codec::{check_capacity, check_len, expect_action, CodecError, Decode, Encode};
+use super::Action;
+
+/// BEP 15: "Up to about 74 torrents can be scraped at once"
+pub const MAX_SCRAPE_HASHES: usize = 74;
+
+#[doc = r"spec described at at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+
+               scrape input
+Offset      Size    Type    Name            Value
+0           64-bit  integer connection_id
+8           32-bit  integer action          2
+12          32-bit  integer transaction_id
+16 + 20 * n 20-byte string  info_hash
+16 + 20 * N"]
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct ScrapeInput<'a> {
+    connection_id: u64,
+    action: Action,
+    transaction_id: u32,
+    pub(super) info_hashes: Cow<'a, [InfoHash]>,
+}
+
+impl<'a> ScrapeInput<'a> {
+    pub(super) fn new(
+        connection_id: u64,
+        transaction_id: u32,
+        info_hashes: &'a [InfoHash],
+    ) -> Result<Self, CodecError> {
+        check_batch_size(info_hashes.len())?;
+        Ok(Self {
+            connection_id,
+            action: Action::Scrape,
+            transaction_id,
+            info_hashes: Cow::Borrowed(info_hashes),
+        })
+    }
+}
+
+fn check_batch_size(len: usize) -> Result<(), CodecError> {
+    if len == 0 || len > MAX_SCRAPE_HASHES {
+        return Err(CodecError::TooManyInfoHashes(len));
+    }
+    Ok(())
+}
+
+impl Encode for ScrapeInput<'_> {
+    fn encoded_len(&self) -> usize {
+        16 + 20 * self.info_hashes.len()
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, CodecError> {
+        check_batch_size(self.info_hashes.len())?;
+        let len = self.encoded_len();
+        check_capacity(buf, len)?;
+        let mut buf = &mut buf[..];
+        buf.put_u64(self.connection_id);
+        buf.put_u32(self.action as u32);
+        buf.put_u32(self.transaction_id);
+        for hash in self.info_hashes.iter() {
+            buf.put_slice(&hash.0);
+        }
+        Ok(len)
+    }
+}
+
+impl Decode for ScrapeInput<'static> {
+    fn decode(mut buf: &[u8]) -> Result<Self, CodecError> {
+        check_len(buf, "scrape input", |len| {
+            len > 16 && (len - 16).is_multiple_of(20)
+        })?;
+        let connection_id = buf.get_u64();
+        expect_action(buf.get_u32(), Action::Scrape)?;
+        let transaction_id = buf.get_u32();
+        check_batch_size(buf.len() / 20)?;
+        let info_hashes = buf
+            .chunks_exact(20)
+            .map(|chunk| InfoHash(chunk.try_into().unwrap()))
+            .collect();
+
+        Ok(Self {
+            connection_id,
+            action: Action::Scrape,
+            transaction_id,
+            info_hashes: Cow::Owned(info_hashes),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScrapeStats {
+    pub seeders: u32,
+    pub completed: u32,
+    pub leechers: u32,
+}
+
+#[doc = r"spec described at at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+
+               scrape output
+Offset      Size    Type    Name            Value
+0           32-bit  integer action          2
+4           32-bit  integer transaction_id
+8 + 12 * n  32-bit  integer seeders
+12 + 12 * n 32-bit  integer completed
+16 + 12 * n 32-bit  integer leechers
+8 + 12 * N
+
+Stats come in the same order as the infohashes of the request."]
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScrapeOutput {
+    action: Action,
+    transaction_id: u32,
+    pub stats: Vec<ScrapeStats>,
+}
+
+impl Encode for ScrapeOutput {
+    fn encoded_len(&self) -> usize {
+        8 + 12 * self.stats.len()
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, CodecError> {
+        let len = self.encoded_len();
+        check_capacity(buf, len)?;
+        let mut buf = &mut buf[..];
+        buf.put_u32(self.action as u32);
+        buf.put_u32(self.transaction_id);
+        for stats in &self.stats {
+            buf.put_u32(stats.seeders);
+            buf.put_u32(stats.completed);
+            buf.put_u32(stats.leechers);
+        }
+        Ok(len)
+    }
+}
+
+impl Decode for ScrapeOutput {
+    fn decode(mut buf: &[u8]) -> Result<Self, CodecError> {
+        check_len(buf, "scrape output", |len| {
+            len >= 8 && (len - 8).is_multiple_of(12)
+        })?;
+        expect_action(buf.get_u32(), Action::Scrape)?;
+        let transaction_id = buf.get_u32();
+        let mut stats = Vec::with_capacity(buf.len() / 12);
+        while buf.has_remaining() {
+            stats.push(ScrapeStats {
+                seeders: buf.get_u32(),
+                completed: buf.get_u32(),
+                leechers: buf.get_u32(),
+            });
+        }
+
+        Ok(Self {
+            action: Action::Scrape,
+            transaction_id,
+            stats,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use byteorder::{BigEndian, ByteOrder};
+    use proptest::prelude::*;
+
+    use super::{ScrapeInput, ScrapeOutput, ScrapeStats, MAX_SCRAPE_HASHES};
+    use crate::torrent::udp::announce::InfoHash;
+    use crate::torrent::udp::codec::tests::{assert_rejects_truncation, assert_round_trip};
+    use crate::torrent::udp::codec::{CodecError, Decode, Encode};
+    use crate::torrent::udp::Action;
+
+    #[test]
+    fn test_scrape_input() {
+        let connection_id: u64 = 0x1234;
+        let transaction_id: u32 = 72;
+        let hashes = [InfoHash([1; 20]), InfoHash([2; 20])];
+
+        let scrape_input = ScrapeInput::new(connection_id, transaction_id, &hashes).unwrap();
+
+        // Following spec at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+        let mut expected = vec![0; 56];
+        BigEndian::write_u64(&mut expected[0..8], connection_id);
+        BigEndian::write_u32(&mut expected[8..12], Action::Scrape as u32);
+        BigEndian::write_u32(&mut expected[12..16], transaction_id);
+        expected[16..36].copy_from_slice(&[1; 20]);
+        expected[36..56].copy_from_slice(&[2; 20]);
+
+        let mut actual = vec![0; scrape_input.encoded_len()];
+        assert_eq!(scrape_input.encode(&mut actual), Ok(56));
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_scrape_input_batch_size() {
+        let hashes = vec![InfoHash([0; 20]); MAX_SCRAPE_HASHES + 1];
+
+        assert!(ScrapeInput::new(0, 0, &hashes[..MAX_SCRAPE_HASHES]).is_ok());
+        assert_eq!(
+            ScrapeInput::new(0, 0, &hashes),
+            Err(CodecError::TooManyInfoHashes(MAX_SCRAPE_HASHES + 1))
+        );
+        assert!(ScrapeInput::new(0, 0, &[]).is_err());
+
+        let full = ScrapeInput::new(0, 0, &hashes[..MAX_SCRAPE_HASHES]).unwrap();
+        assert!(full.encoded_len() <= 1500);
+    }
+
+    #[test]
+    fn test_scrape_output() {
+        let action = Action::Scrape;
+        let transaction_id: u32 = 42;
+
+        // Following spec at: https://xbtt.sourceforge.net/udp_tracker_protocol.html
+        let mut buf = vec![0; 32];
+        BigEndian::write_u32(&mut buf[0..4], action as u32);
+        BigEndian::write_u32(&mut buf[4..8], transaction_id);
+        BigEndian::write_u32(&mut buf[8..12], 10);
+        BigEndian::write_u32(&mut buf[12..16], 20);
+        BigEndian::write_u32(&mut buf[16..20], 30);
+        BigEndian::write_u32(&mut buf[20..24], 0);
+        BigEndian::write_u32(&mut buf[24..28], 1);
+        BigEndian::write_u32(&mut buf[28..32], 2);
+
+        let expected = ScrapeOutput {
+            action,
+            transaction_id,
+            stats: vec![
+                ScrapeStats {
+                    seeders: 10,
+                    completed: 20,
+                    leechers: 30,
+                },
+                ScrapeStats {
+                    seeders: 0,
+                    completed: 1,
+                    leechers: 2,
+                },
+            ],
+        };
+
+        assert_eq!(Ok(expected), ScrapeOutput::decode(&buf));
+        assert!(ScrapeOutput::decode(&buf[..31]).is_err());
+    }
+
+    fn info_hashes() -> impl Strategy<Value = Vec<InfoHash>> {
+        proptest::collection::vec(any::<[u8; 20]>().prop_map(InfoHash), 1..=MAX_SCRAPE_HASHES)
+    }
+
+    proptest! {
+        #[test]
+        fn scrape_input_round_trip(
+            connection_id in any::<u64>(),
+            transaction_id in any::<u32>(),
+            hashes in info_hashes(),
+        ) {
+            let message = ScrapeInput {
+                connection_id,
+                action: Action::Scrape,
+                transaction_id,
+                info_hashes: Cow::Owned(hashes),
+            };
+            assert_round_trip(&message);
+            assert_rejects_truncation(&message, 36);
+        }
+
+        #[test]
+        fn scrape_output_round_trip(
+            transaction_id in any::<u32>(),
+            stats in proptest::collection::vec(any::<(u32, u32, u32)>(), 1..=MAX_SCRAPE_HASHES),
+        ) {
+            let message = ScrapeOutput {
+                action: Action::Scrape,
+                transaction_id,
+                stats: stats
+                    .into_iter()
+                    .map(|(seeders, completed, leechers)| ScrapeStats {
+                        seeders,
+                        completed,
+                        leechers,
+                    })
+                    .collect(),
+            };
+            assert_round_trip(&message);
+            assert_rejects_truncation(&message, 8);
+        }
+
+        #[test]
+        fn scrape_never_panics(buf in proptest::collection::vec(any::<u8>(), 0..128)) {
+            let _ = ScrapeInput::decode(&buf);
+            let _ = ScrapeOutput::decode(&buf);
+        }
+    }
+}
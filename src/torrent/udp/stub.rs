@@ -0,0 +1,147 @@
+//! A local stand-in for a UDP tracker, used by the tests
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use byteorder::{BigEndian, ByteOrder};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+use super::{Action, CONNECTION_ID};
+
+/// Handed out to clients in place of the protocol id they connect with
+pub(crate) const STUB_CONNECTION_ID: u64 = 0x1234_5678_9abc_def0;
+
+#[derive(Default)]
+struct Counters {
+    received: AtomicUsize,
+    connects: AtomicUsize,
+    drop_next: AtomicUsize,
+    stray_packets: AtomicUsize,
+    error: Mutex<Option<String>>,
+}
+
+/// Answers connect requests with `STUB_CONNECTION_ID`, announces with `b`
+/// seeders, `3b` leechers and a single peer, and scrapes with `(b, 2b, 3b)`
+/// seeders/completed/leechers, `b` being the first byte of each infohash.
+pub(crate) struct StubTracker {
+    pub addr: SocketAddr,
+    counters: Arc<Counters>,
+    handle: JoinHandle<()>,
+}
+
+impl StubTracker {
+    pub async fn spawn() -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let counters = Arc::new(Counters::default());
+
+        let handle = tokio::spawn({
+            let counters = counters.clone();
+            async move {
+                let mut buf = vec![0; 65_536];
+                loop {
+                    let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+                        return;
+                    };
+                    counters.received.fetch_add(1, Ordering::SeqCst);
+                    let dropping =
+                        counters
+                            .drop_next
+                            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+                    if dropping.is_ok() {
+                        continue;
+                    }
+
+                    let response = respond(&buf[..len], &counters);
+                    if counters.stray_packets.load(Ordering::SeqCst) > 0 {
+                        let mut stray = response.clone();
+                        let transaction_id = BigEndian::read_u32(&stray[4..8]);
+                        BigEndian::write_u32(&mut stray[4..8], transaction_id.wrapping_add(1));
+                        socket.send_to(&stray, peer).await.unwrap();
+                    }
+                    socket.send_to(&response, peer).await.unwrap();
+                }
+            }
+        });
+
+        Self {
+            addr,
+            counters,
+            handle,
+        }
+    }
+
+    /// Silently drops the next `n` packets
+    pub fn drop_next(self, n: usize) -> Self {
+        self.counters.drop_next.store(n, Ordering::SeqCst);
+        self
+    }
+
+    /// Answers everything but connect requests with an error packet
+    pub fn fail_with(self, message: &str) -> Self {
+        *self.counters.error.lock().unwrap() = Some(message.to_string());
+        self
+    }
+
+    /// Precedes every response with a copy carrying a different transaction_id
+    pub fn send_stray_packets(self) -> Self {
+        self.counters.stray_packets.store(1, Ordering::SeqCst);
+        self
+    }
+
+    pub fn received(&self) -> usize {
+        self.counters.received.load(Ordering::SeqCst)
+    }
+
+    pub fn connects(&self) -> usize {
+        self.counters.connects.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for StubTracker {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn respond(request: &[u8], counters: &Counters) -> Vec<u8> {
+    let connection_id = BigEndian::read_u64(&request[0..8]);
+    let action = BigEndian::read_u32(&request[8..12]);
+    let transaction_id = BigEndian::read_u32(&request[12..16]);
+
+    let mut response = vec![0; 8];
+    BigEndian::write_u32(&mut response[0..4], action);
+    BigEndian::write_u32(&mut response[4..8], transaction_id);
+
+    let error = counters.error.lock().unwrap().clone();
+    match Action::try_from(action) {
+        Ok(Action::Connect) if connection_id == CONNECTION_ID => {
+            counters.connects.fetch_add(1, Ordering::SeqCst);
+            response.extend_from_slice(&STUB_CONNECTION_ID.to_be_bytes());
+        }
+        Ok(_) if connection_id == STUB_CONNECTION_ID && error.is_some() => {
+            BigEndian::write_u32(&mut response[0..4], Action::Error as u32);
+            response.extend_from_slice(error.unwrap().as_bytes());
+        }
+        Ok(Action::Announce) if connection_id == STUB_CONNECTION_ID => {
+            let b = request[16] as u32;
+            for n in [1800, 3 * b, b] {
+                response.extend_from_slice(&n.to_be_bytes());
+            }
+            response.extend_from_slice(&[10, 0, 0, 1, 0x1a, 0xe1]);
+        }
+        Ok(Action::Scrape) if connection_id == STUB_CONNECTION_ID => {
+            for hash in request[16..].chunks_exact(20) {
+                let b = hash[0] as u32;
+                for n in [b, 2 * b, 3 * b] {
+                    response.extend_from_slice(&n.to_be_bytes());
+                }
+            }
+        }
+        _ => panic!("unexpected request: {:?}", request),
+    }
+
+    response
+}
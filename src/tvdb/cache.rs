@@ -0,0 +1,255 @@
+//! In-memory cache in front of the Sonarr/Radarr id lookups. A season search
+//! sends one request per episode, and without it every one of them would ask
+//! Sonarr about the same show.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::OnceCell;
+use tokio::time::Instant;
+
+use crate::config::LookupCacheConfig;
+
+#[derive(Debug)]
+pub struct LookupCache<K, V> {
+    /// An empty cell is a lookup in flight, or one that failed
+    entries: Mutex<HashMap<K, Arc<OnceCell<Cached<V>>>>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+    max_entries: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Cached<V> {
+    /// `None` when the lookup found nothing
+    value: Option<V>,
+    expires: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl<K, V> LookupCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new(config: &LookupCacheConfig) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl: Duration::from_secs(config.ttl),
+            negative_ttl: Duration::from_secs(config.negative_ttl),
+            max_entries: config.max_entries,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached value of `key`, or the result of `fetch`. Concurrent calls
+    /// for the same key share a single `fetch`. Errors aren't cached, the
+    /// next call fetches again. When the cache is full of lookups still in
+    /// flight, `fetch` runs without it.
+    pub async fn get_or_fetch<F, Fut>(&self, key: K, fetch: F) -> anyhow::Result<Option<V>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<Option<V>>>,
+    {
+        let Some(cell) = self.cell(key) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return fetch().await;
+        };
+        let mut fetched = false;
+        let cached = cell
+            .get_or_try_init(|| {
+                fetched = true;
+                async {
+                    let value = fetch().await?;
+                    let ttl = match value {
+                        Some(_) => self.ttl,
+                        None => self.negative_ttl,
+                    };
+                    Ok::<_, anyhow::Error>(Cached {
+                        value,
+                        expires: Instant::now() + ttl,
+                    })
+                }
+            })
+            .await?;
+
+        let counter = if fetched { &self.misses } else { &self.hits };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Ok(cached.value.clone())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.lock().len(),
+        }
+    }
+
+    /// The cell holding `key`, fresh or in flight. `None` when the cache is
+    /// full and nothing in it can be evicted.
+    fn cell(&self, key: K) -> Option<Arc<OnceCell<Cached<V>>>> {
+        let now = Instant::now();
+        let mut entries = self.lock();
+        if let Some(cell) = entries.get(&key) {
+            if cell.get().is_none_or(|cached| cached.expires > now) {
+                return Some(cell.clone());
+            }
+        }
+
+        if entries.len() >= self.max_entries {
+            // Nobody else holds an empty cell once its lookup failed
+            entries.retain(|_, cell| match cell.get() {
+                Some(cached) => cached.expires > now,
+                None => Arc::strong_count(cell) > 1,
+            });
+        }
+        if entries.len() >= self.max_entries {
+            let soonest = entries
+                .iter()
+                .filter_map(|(key, cell)| Some((key, cell.get()?.expires)))
+                .min_by_key(|&(_, expires)| expires)
+                .map(|(key, _)| key.clone())?;
+            entries.remove(&soonest);
+        }
+
+        let cell = Arc::new(OnceCell::new());
+        entries.insert(key, cell.clone());
+        Some(cell)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<K, Arc<OnceCell<Cached<V>>>>> {
+        // The map is never left half-updated, a panic elsewhere can't hurt it
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{CacheStats, LookupCache};
+    use crate::config::LookupCacheConfig;
+
+    fn cache(negative_ttl: u64, max_entries: usize) -> Arc<LookupCache<u32, String>> {
+        Arc::new(LookupCache::new(&LookupCacheConfig {
+            ttl: 3600,
+            negative_ttl,
+            max_entries,
+            persist: false,
+        }))
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_lookups() {
+        let cache = cache(60, 10);
+        let fetches = Arc::new(AtomicU32::new(0));
+
+        let lookups = (0..10).map(|_| {
+            let (cache, fetches) = (cache.clone(), fetches.clone());
+            tokio::spawn(async move {
+                cache
+                    .get_or_fetch(1, || async move {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Ok(Some("tt0903747".to_string()))
+                    })
+                    .await
+                    .unwrap()
+            })
+        });
+        for lookup in lookups.collect::<Vec<_>>() {
+            assert_eq!(lookup.await.unwrap().as_deref(), Some("tt0903747"));
+        }
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 9,
+                misses: 1,
+                entries: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn caches_not_found_briefly() {
+        let cache = cache(0, 10);
+        let not_found = || async { Ok(None) };
+        let found = || async { Ok(Some("tt0133093".to_string())) };
+
+        assert_eq!(cache.get_or_fetch(1, not_found).await.unwrap(), None);
+        // Expired right away
+        assert!(cache.get_or_fetch(1, found).await.unwrap().is_some());
+        assert!(cache.get_or_fetch(1, not_found).await.unwrap().is_some());
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_cache_errors() {
+        let cache = cache(60, 10);
+        let failed = cache
+            .get_or_fetch(1, || async { anyhow::bail!("Sonarr is down") })
+            .await;
+        assert!(failed.is_err());
+
+        let retried = cache
+            .get_or_fetch(1, || async { Ok(Some("tt0386676".to_string())) })
+            .await;
+        assert_eq!(retried.unwrap().as_deref(), Some("tt0386676"));
+    }
+
+    #[tokio::test]
+    async fn stays_within_max_entries() {
+        let cache = cache(60, 2);
+        for key in 0..5 {
+            cache
+                .get_or_fetch(key, || async move { Ok(Some(key.to_string())) })
+                .await
+                .unwrap();
+        }
+        assert_eq!(cache.stats().entries, 2);
+    }
+
+    #[tokio::test]
+    async fn bypasses_a_cache_full_of_lookups_in_flight() {
+        let cache = cache(60, 1);
+        let slow = tokio::spawn({
+            let cache = cache.clone();
+            async move {
+                cache
+                    .get_or_fetch(0, || async {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        Ok(Some("tt0903747".to_string()))
+                    })
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let fetched = cache
+            .get_or_fetch(1, || async { Ok(Some("tt0133093".to_string())) })
+            .await;
+        assert_eq!(fetched.unwrap().as_deref(), Some("tt0133093"));
+        assert_eq!(cache.stats().entries, 1);
+        assert!(slow.await.unwrap().is_ok());
+        assert_eq!(cache.stats().misses, 2);
+    }
+}
@@ -1,16 +1,27 @@
-use reqwest::{IntoUrl, Method};
+use reqwest::{IntoUrl, Method, StatusCode};
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 
+use crate::config::{RadarrConfig, SonarrConfig};
+
+pub mod cache;
+
 #[derive(Debug, Deserialize)]
 pub struct Series {
-    pub title: String,
     #[serde(rename = "imdbId")]
     pub imdb_id: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Movie {
+    #[serde(rename = "imdbId")]
+    pub imdb_id: Option<String>,
+}
+
+/// What Sonarr and Radarr have in common: an API key header in front of the
+/// same `/api/v3` layout
 #[derive(Debug, Clone)]
-pub struct SonarrClient {
+struct ArrClient {
     api_key: Secret<String>,
     base_url: String,
     client: reqwest::Client,
@@ -18,13 +29,13 @@ pub struct SonarrClient {
 
 const API_KEY_HEADER: &str = "X-Api-Key";
 
-impl SonarrClient {
-    pub fn new(base_url: String, api_key: String) -> anyhow::Result<Self> {
-        Ok(Self {
-            api_key: Secret::new(api_key),
-            base_url,
+impl ArrClient {
+    fn new(url: &str, api_key: Secret<String>) -> Self {
+        Self {
+            api_key,
+            base_url: url.trim_end_matches('/').to_string(),
             client: reqwest::Client::new(),
-        })
+        }
     }
 
     fn request<U: IntoUrl>(&self, method: reqwest::Method, url: U) -> reqwest::RequestBuilder {
@@ -32,14 +43,28 @@ impl SonarrClient {
             .request(method, url)
             .header(API_KEY_HEADER, self.api_key.expose_secret())
     }
+}
+
+#[derive(Debug, Clone)]
+pub struct SonarrClient(ArrClient);
+
+impl SonarrClient {
+    pub fn new(config: &SonarrConfig) -> anyhow::Result<Self> {
+        let api_key = config
+            .api_key
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("missing Sonarr API key"))?;
+        Ok(Self(ArrClient::new(&config.url, api_key)))
+    }
 
     pub async fn get_series_metadata(&self, tvdb_id: &str) -> anyhow::Result<Vec<Series>> {
         let series = self
+            .0
             .request(
                 Method::GET,
                 format!(
                     "{}/api/v3/series/lookup?term=tvdb:{}",
-                    self.base_url, tvdb_id
+                    self.0.base_url, tvdb_id
                 ),
             )
             .send()
@@ -50,3 +75,59 @@ impl SonarrClient {
         Ok(series)
     }
 }
+
+#[derive(Debug, Clone)]
+pub struct RadarrClient(ArrClient);
+
+impl RadarrClient {
+    /// `None` when no API key is configured
+    pub fn new(config: &RadarrConfig) -> Option<Self> {
+        let api_key = config.api_key.clone()?;
+        Some(Self(ArrClient::new(&config.url, api_key)))
+    }
+
+    /// `None` when TMDB doesn't know the id
+    pub async fn get_movie_metadata(&self, tmdb_id: &str) -> anyhow::Result<Option<Movie>> {
+        let response = self
+            .0
+            .request(
+                Method::GET,
+                format!(
+                    "{}/api/v3/movie/lookup/tmdb?tmdbId={}",
+                    self.0.base_url, tmdb_id
+                ),
+            )
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        Ok(Some(response.error_for_status()?.json::<Movie>().await?))
+    }
+}
+
+/// Accepts `tt0133093`, `133093` and the like
+pub fn normalize_imdb_id(id: &str) -> Option<String> {
+    let digits = id.trim().trim_start_matches("tt");
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("tt{digits:0>7}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_imdb_id;
+
+    #[test]
+    fn normalizes_imdb_ids() {
+        assert_eq!(normalize_imdb_id("tt0133093").as_deref(), Some("tt0133093"));
+        assert_eq!(normalize_imdb_id("133093").as_deref(), Some("tt0133093"));
+        assert_eq!(
+            normalize_imdb_id("tt12345678").as_deref(),
+            Some("tt12345678")
+        );
+        assert_eq!(normalize_imdb_id("nope"), None);
+    }
+}